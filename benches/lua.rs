@@ -0,0 +1,56 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use lua::{
+    lex::{Lex, Token},
+    parse::Parser,
+};
+
+/// Classic Lua benchmark programs, also used by the compiler and VM
+/// groups once those exist
+const SCRIPTS: &[(&str, &str)] = &[
+    ("fib", include_str!("lua/fib.lua")),
+    ("nbody", include_str!("lua/nbody.lua")),
+    ("spectral_norm", include_str!("lua/spectral_norm.lua")),
+    ("binary_trees", include_str!("lua/binary_trees.lua")),
+    ("strings", include_str!("lua/strings.lua")),
+];
+
+fn lex_all(source: &str) -> usize {
+    let mut lex = Lex::new(source);
+    let mut count = 0;
+    while lex.next().unwrap() != Token::Eof {
+        count += 1;
+    }
+    count
+}
+
+fn bench_lex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for (name, source) in SCRIPTS {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| lex_all(black_box(source))));
+    }
+    group.finish();
+}
+
+fn bench_parse(c: &mut Criterion) {
+    // The parser only handles call statements so far, so the classic
+    // programs can't go through it yet. Use a call-heavy chunk instead.
+    let source = "print(x, f(1, 2.5), g(h(nil, false)))\n".repeat(1000);
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("calls", |b| {
+        b.iter(|| {
+            Parser::new(Lex::new(black_box(&source)))
+                .unwrap()
+                .parse()
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_lex, bench_parse);
+criterion_main!(benches);
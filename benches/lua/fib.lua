@@ -0,0 +1,10 @@
+local function fib(n)
+  if n < 2 then
+    return n
+  end
+  return fib(n - 1) + fib(n - 2)
+end
+
+local start = os.clock()
+local result = fib(32)
+print("fib(32) = " .. result, os.clock() - start)
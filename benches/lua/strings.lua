@@ -0,0 +1,22 @@
+local words = {}
+for i = 1, 2000 do
+  words[#words + 1] = "word" .. i
+end
+
+local joined = table.concat(words, " ")
+local count = 0
+for w in string.gmatch(joined, "%a+%d+") do
+  count = count + #w
+end
+
+local s = ""
+for i = 1, 500 do
+  s = s .. string.upper(words[i]) .. ","
+end
+
+local replaced, n = string.gsub(s, "WORD", "w")
+local reversed = string.reverse(replaced)
+local found = string.find(reversed, ",1w", 1, true)
+
+print(count, #s, n, #reversed, found)
+print(string.format("%5.2f %s %d", 3.14159, string.rep("ab", 3, "-"), #joined))
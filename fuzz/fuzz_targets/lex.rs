@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lua::lex::{Lex, Token};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    // Every token consumes at least one byte, so the lexer must reach Eof
+    // (or an error) within that many calls
+    let mut lex = Lex::new(source);
+    for _ in 0..=source.len() {
+        match lex.next() {
+            Ok(Token::Eof) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+    panic!("lexer did not terminate");
+});
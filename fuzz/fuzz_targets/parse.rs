@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lua::{lex::Lex, parse::Parser};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(mut parser) = Parser::new(Lex::new(source)) else {
+        return;
+    };
+    let Ok(stmts) = parser.parse() else {
+        return;
+    };
+
+    // Spans are byte offsets of the statements, in order
+    let mut prev_end = 0;
+    for stmt in &stmts {
+        let span = stmt.span;
+        assert!(prev_end <= span.start && span.start <= span.end, "{span:?}");
+        assert!(span.end as usize <= source.len(), "{span:?}");
+        prev_end = span.end;
+    }
+});
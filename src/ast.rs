@@ -8,6 +8,221 @@ impl Span {
     pub fn new(start: u32, end: u32) -> Self {
         Self { start, end }
     }
+
+    pub fn shifted(self, delta: i64) -> Self {
+        let shift = |offset: u32| (offset as i64 + delta) as u32;
+        Self::new(shift(self.start), shift(self.end))
+    }
+}
+
+/// Replacement of the bytes in `span` with `text`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn new(span: Span, text: impl Into<String>) -> Self {
+        Self {
+            span,
+            text: text.into(),
+        }
+    }
+
+    /// Apply the edit to the source it was made against
+    pub fn apply(&self, source: &str) -> String {
+        let (start, end) = (self.span.start as usize, self.span.end as usize);
+        [&source[..start], &self.text, &source[end..]].concat()
+    }
+}
+
+/// Identifies a node of one parse, to key side tables like `NodeMap`.
+/// The parser numbers nodes from 0 in the order it finishes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+    /// The id of nodes built outside the parser
+    pub const DUMMY: NodeId = NodeId(u32::MAX);
+}
+
+/// Per-node data kept beside the tree, e.g. the results of an analysis.
+/// Stored densely by id, so it suits data most nodes have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeMap<T> {
+    values: Vec<Option<T>>,
+}
+
+impl<T> Default for NodeMap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> NodeMap<T> {
+    pub fn new() -> Self {
+        Self { values: Vec::new() }
+    }
+
+    /// Set the value for `id`, returning the old one. Panics on
+    /// `NodeId::DUMMY`.
+    pub fn insert(&mut self, id: NodeId, value: T) -> Option<T> {
+        assert_ne!(id, NodeId::DUMMY, "dummy node id in a NodeMap");
+        let index = id.0 as usize;
+        if index >= self.values.len() {
+            self.values.resize_with(index + 1, || None);
+        }
+        self.values[index].replace(value)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&T> {
+        self.values.get(id.0 as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut T> {
+        self.values.get_mut(id.0 as usize)?.as_mut()
+    }
+
+    pub fn remove(&mut self, id: NodeId) -> Option<T> {
+        self.values.get_mut(id.0 as usize)?.take()
+    }
+
+    pub fn contains_key(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Entries in id order
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &T)> {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| Some((NodeId(i as u32), value.as_ref()?)))
+    }
+}
+
+/// A reference to either kind of node, for `walk`
+#[derive(Debug, Clone, Copy)]
+pub enum Node<'a> {
+    Expr(&'a ExprNode),
+    Stmt(&'a StmtNode),
+}
+
+impl Node<'_> {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Node::Expr(expr) => expr.id,
+            Node::Stmt(stmt) => stmt.id,
+        }
+    }
+}
+
+/// Call `f` on every node of `block`, each before its children
+pub fn walk<'a>(block: &'a [StmtNode], f: &mut impl FnMut(Node<'a>)) {
+    for stmt in block {
+        walk_stmt(stmt, f);
+    }
+}
+
+fn walk_exprs<'a>(exprs: &'a [ExprNode], f: &mut impl FnMut(Node<'a>)) {
+    for expr in exprs {
+        walk_expr(expr, f);
+    }
+}
+
+fn walk_stmt<'a>(stmt: &'a StmtNode, f: &mut impl FnMut(Node<'a>)) {
+    f(Node::Stmt(stmt));
+    match &stmt.stmt {
+        Stmt::Break => {}
+        Stmt::Return(exprs) | Stmt::LocalAssign(_, exprs) => walk_exprs(exprs, f),
+        Stmt::Assign(targets, exprs) => {
+            walk_exprs(targets, f);
+            walk_exprs(exprs, f);
+        }
+        Stmt::FuncCall(expr) | Stmt::MethodCall(expr) => walk_expr(expr, f),
+        Stmt::DoBlock(body) => walk(body, f),
+        Stmt::If(if_stmt) => {
+            walk_expr(&if_stmt.cond, f);
+            walk(&if_stmt.then_branch, f);
+            walk(&if_stmt.else_branch, f);
+        }
+        Stmt::While(cond, body) | Stmt::Repeat(cond, body) => {
+            walk_expr(cond, f);
+            walk(body, f);
+        }
+        Stmt::NumberFor(for_stmt) => {
+            walk_expr(&for_stmt.init, f);
+            walk_expr(&for_stmt.limit, f);
+            walk_expr(&for_stmt.step, f);
+            walk(&for_stmt.body, f);
+        }
+        Stmt::GenericFor(for_stmt) => {
+            walk_exprs(&for_stmt.exprs, f);
+            walk(&for_stmt.body, f);
+        }
+        Stmt::FuncDef(def) => {
+            walk_expr(&def.name, f);
+            walk_expr(&def.body, f);
+        }
+        Stmt::MethodDef(def) => {
+            walk_expr(&def.obj, f);
+            walk_expr(&def.body, f);
+        }
+    }
+}
+
+fn walk_expr<'a>(expr: &'a ExprNode, f: &mut impl FnMut(Node<'a>)) {
+    f(Node::Expr(expr));
+    match &expr.expr {
+        Expr::UnaryOp(_, operand) => walk_expr(operand, f),
+        Expr::BinaryOp(_, lhs, rhs) | Expr::AttrGet(lhs, rhs) => {
+            walk_expr(lhs, f);
+            walk_expr(rhs, f);
+        }
+        Expr::FuncCall(func, args) | Expr::MethodCall(func, _, args) => {
+            walk_expr(func, f);
+            walk_exprs(args, f);
+        }
+        Expr::Table(fields) => {
+            for field in fields {
+                if let Some(key) = &field.key {
+                    walk_expr(key, f);
+                }
+                walk_expr(&field.val, f);
+            }
+        }
+        Expr::Function(_, body) => walk(body, f),
+        Expr::Nil
+        | Expr::Bool(_)
+        | Expr::Integer(_)
+        | Expr::Float(_)
+        | Expr::String(_)
+        | Expr::Dots
+        | Expr::Ident(_) => {}
+    }
+}
+
+/// Source text around a node, only recorded by a lossless `Parser`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trivia {
+    /// Whitespace and comments before the node, verbatim
+    pub leading: String,
+    /// The node's own source text, verbatim (e.g. `0x10` rather than `16`)
+    pub text: String,
+}
+
+/// Reproduce the source of a chunk parsed in lossless mode from its
+/// statements and the parser's trailing trivia. Returns `None` if any
+/// statement has no trivia recorded.
+pub fn lossless_source(block: &[StmtNode], trailing: &str) -> Option<String> {
+    let mut source = String::new();
+    for stmt in block {
+        let trivia = stmt.trivia.as_ref()?;
+        source.push_str(&trivia.leading);
+        source.push_str(&trivia.text);
+    }
+    source.push_str(trailing);
+    Some(source)
 }
 
 /// Unary operators
@@ -59,22 +274,78 @@ pub enum Expr {
     Function(ParList, Vec<StmtNode>),
 }
 
-/// A wrapper that stores the expression and its span
+/// A wrapper that stores the expression and its span (byte offsets)
 #[derive(Debug, Clone)]
 pub struct ExprNode {
+    pub id: NodeId,
     pub expr: Expr,
     pub span: Span,
+    pub trivia: Option<Box<Trivia>>,
 }
 
 impl ExprNode {
     pub fn new(expr: Expr, span: (u32, u32)) -> Self {
         Self {
+            id: NodeId::DUMMY,
             expr,
             span: Span::new(span.0, span.1),
+            trivia: None,
+        }
+    }
+
+    /// Move the node and everything under it by `delta` bytes
+    pub fn shift(&mut self, delta: i64) {
+        self.span = self.span.shifted(delta);
+        match &mut self.expr {
+            Expr::UnaryOp(_, expr) => expr.shift(delta),
+            Expr::BinaryOp(_, lhs, rhs) => {
+                lhs.shift(delta);
+                rhs.shift(delta);
+            }
+            Expr::FuncCall(func, args) => {
+                func.shift(delta);
+                shift_exprs(args, delta);
+            }
+            Expr::MethodCall(obj, _, args) => {
+                obj.shift(delta);
+                shift_exprs(args, delta);
+            }
+            Expr::AttrGet(obj, key) => {
+                obj.shift(delta);
+                key.shift(delta);
+            }
+            Expr::Table(fields) => {
+                for field in fields {
+                    if let Some(key) = &mut field.key {
+                        key.shift(delta);
+                    }
+                    field.val.shift(delta);
+                }
+            }
+            Expr::Function(_, body) => shift_block(body, delta),
+            Expr::Nil
+            | Expr::Bool(_)
+            | Expr::Integer(_)
+            | Expr::Float(_)
+            | Expr::String(_)
+            | Expr::Dots
+            | Expr::Ident(_) => {}
         }
     }
 }
 
+fn shift_exprs(exprs: &mut [ExprNode], delta: i64) {
+    for expr in exprs {
+        expr.shift(delta);
+    }
+}
+
+fn shift_block(block: &mut [StmtNode], delta: i64) {
+    for stmt in block {
+        stmt.shift(delta);
+    }
+}
+
 /// Table fields
 #[derive(Debug, Clone)]
 pub struct Field {
@@ -95,6 +366,12 @@ pub struct ParList {
     pub varargs: bool,
 }
 
+impl Default for ParList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ParList {
     pub fn new() -> Self {
         Self {
@@ -131,18 +408,64 @@ pub enum Stmt {
     MethodDef(MethodDef),
 }
 
-/// A wrapper storing a statement and its span
+/// A wrapper storing a statement and its span (byte offsets)
 #[derive(Debug, Clone)]
 pub struct StmtNode {
+    pub id: NodeId,
     pub stmt: Stmt,
     pub span: Span,
+    pub trivia: Option<Box<Trivia>>,
 }
 
 impl StmtNode {
     pub fn new(stmt: Stmt, span: (u32, u32)) -> Self {
         Self {
+            id: NodeId::DUMMY,
             stmt,
             span: Span::new(span.0, span.1),
+            trivia: None,
+        }
+    }
+
+    /// Move the node and everything under it by `delta` bytes
+    pub fn shift(&mut self, delta: i64) {
+        self.span = self.span.shifted(delta);
+        match &mut self.stmt {
+            Stmt::Break => {}
+            Stmt::Return(exprs) | Stmt::LocalAssign(_, exprs) => shift_exprs(exprs, delta),
+            Stmt::Assign(targets, exprs) => {
+                shift_exprs(targets, delta);
+                shift_exprs(exprs, delta);
+            }
+            Stmt::FuncCall(expr) | Stmt::MethodCall(expr) => expr.shift(delta),
+            Stmt::DoBlock(body) => shift_block(body, delta),
+            Stmt::If(if_stmt) => {
+                if_stmt.cond.shift(delta);
+                shift_block(&mut if_stmt.then_branch, delta);
+                shift_block(&mut if_stmt.else_branch, delta);
+            }
+            Stmt::While(cond, body) | Stmt::Repeat(cond, body) => {
+                cond.shift(delta);
+                shift_block(body, delta);
+            }
+            Stmt::NumberFor(for_stmt) => {
+                for_stmt.init.shift(delta);
+                for_stmt.limit.shift(delta);
+                for_stmt.step.shift(delta);
+                shift_block(&mut for_stmt.body, delta);
+            }
+            Stmt::GenericFor(for_stmt) => {
+                shift_exprs(&mut for_stmt.exprs, delta);
+                shift_block(&mut for_stmt.body, delta);
+            }
+            Stmt::FuncDef(def) => {
+                def.name.shift(delta);
+                def.body.shift(delta);
+            }
+            Stmt::MethodDef(def) => {
+                def.obj.shift(delta);
+                def.body.shift(delta);
+            }
         }
     }
 }
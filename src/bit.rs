@@ -0,0 +1,123 @@
+//! LuaJIT's `bit` library. Every operation works on 32-bit signed
+//! integers, so results match LuaJIT even though Lua numbers here are
+//! 64 bits wide.
+
+use crate::number::Number;
+
+/// `bit.tobit`: the low 32 bits of a number. Floats are rounded to the
+/// nearest integer first, and NaN and infinities give 0.
+pub fn tobit(x: Number) -> i32 {
+    match x {
+        Number::Integer(i) => i as i32,
+        Number::Float(f) if f.is_finite() => {
+            f.round_ties_even().rem_euclid(4294967296.0) as u32 as i32
+        }
+        Number::Float(_) => 0,
+    }
+}
+
+pub fn bnot(x: Number) -> i32 {
+    !tobit(x)
+}
+
+pub fn band(xs: &[Number]) -> i32 {
+    xs.iter().fold(-1, |acc, &x| acc & tobit(x))
+}
+
+pub fn bor(xs: &[Number]) -> i32 {
+    xs.iter().fold(0, |acc, &x| acc | tobit(x))
+}
+
+pub fn bxor(xs: &[Number]) -> i32 {
+    xs.iter().fold(0, |acc, &x| acc ^ tobit(x))
+}
+
+/// Shift counts only use their low 5 bits, like the x86 shift instructions
+fn count(n: Number) -> u32 {
+    tobit(n) as u32 & 31
+}
+
+pub fn lshift(x: Number, n: Number) -> i32 {
+    tobit(x) << count(n)
+}
+
+/// Logical right shift
+pub fn rshift(x: Number, n: Number) -> i32 {
+    ((tobit(x) as u32) >> count(n)) as i32
+}
+
+/// Arithmetic right shift, copying the sign bit
+pub fn arshift(x: Number, n: Number) -> i32 {
+    tobit(x) >> count(n)
+}
+
+pub fn rol(x: Number, n: Number) -> i32 {
+    tobit(x).rotate_left(count(n))
+}
+
+pub fn ror(x: Number, n: Number) -> i32 {
+    tobit(x).rotate_right(count(n))
+}
+
+pub fn bswap(x: Number) -> i32 {
+    tobit(x).swap_bytes()
+}
+
+/// `bit.tohex(x, n)`: the low `|n|` hex digits of `x`, at most 8, in upper
+/// case when `n` is negative. `n` defaults to 8.
+pub fn tohex(x: Number, n: Option<Number>) -> String {
+    let n = n.map_or(8, tobit);
+    let digits = (n.unsigned_abs() as usize).min(8);
+    let hex = match n < 0 {
+        true => format!("{:08X}", tobit(x) as u32),
+        false => format!("{:08x}", tobit(x) as u32),
+    };
+    hex[8 - digits..].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn i(n: i64) -> Number {
+        Number::Integer(n)
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(tobit(i(0xffffffff)), -1);
+        assert_eq!(tobit(i(0x100000001)), 1);
+        assert_eq!(tobit(i(-1)), -1);
+        assert_eq!(tobit(Number::Float(2.5)), 2);
+        assert_eq!(tobit(Number::Float(3.5)), 4);
+        assert_eq!(tobit(Number::Float(-1.0)), -1);
+        assert_eq!(tobit(Number::Float(4294967296.0 + 7.0)), 7);
+        assert_eq!(tobit(Number::Float(f64::NAN)), 0);
+        assert_eq!(tobit(Number::Float(f64::INFINITY)), 0);
+    }
+
+    #[test]
+    fn operations() {
+        assert_eq!(band(&[i(0x12345678), i(0xff)]), 0x78);
+        assert_eq!(band(&[]), -1);
+        assert_eq!(bor(&[i(1), i(2), i(4)]), 7);
+        assert_eq!(bxor(&[i(0xa5), i(0xff)]), 0x5a);
+        assert_eq!(bnot(i(0)), -1);
+        assert_eq!(lshift(i(1), i(31)), i32::MIN);
+        assert_eq!(lshift(i(1), i(33)), 2);
+        assert_eq!(rshift(i(-1), i(28)), 0xf);
+        assert_eq!(arshift(i(-256), i(4)), -16);
+        assert_eq!(rol(i(0x12345678), i(12)), 0x45678123);
+        assert_eq!(ror(i(0x12345678), i(12)), 0x67812345);
+        assert_eq!(bswap(i(0x12345678)), 0x78563412);
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(tohex(i(1), None), "00000001");
+        assert_eq!(tohex(i(255), Some(i(-2))), "FF");
+        assert_eq!(tohex(i(-1), Some(i(4))), "ffff");
+        assert_eq!(tohex(i(0x12345678), Some(i(20))), "12345678");
+        assert_eq!(tohex(i(1), Some(i(0))), "");
+    }
+}
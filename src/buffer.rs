@@ -0,0 +1,669 @@
+//! The `buffer` library: a growable string for building output piece by
+//! piece, like LuaJIT's `string.buffer`, so a loop of appends doesn't copy
+//! everything built so far each time the way repeated `..` does.
+//!
+//! `putf` formats like `string.format`, following the reference
+//! `lstrlib.c` for which flags each conversion takes.
+
+use crate::number::{self, Number};
+use crate::value::{self, Scalar};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A `%` directive `putf` doesn't know or whose flags, width or
+    /// precision don't fit it, holding the directive after the `%`
+    InvalidConversion(Vec<u8>),
+    /// `%q` with flags, a width or a precision
+    QuotedWithModifiers,
+    /// An argument error, numbered like the arguments of the method (the
+    /// format is argument 1 of `putf`)
+    BadArgument {
+        arg: usize,
+        func: &'static str,
+        msg: &'static str,
+    },
+    /// An argument of the wrong type
+    WrongType {
+        arg: usize,
+        func: &'static str,
+        expected: &'static str,
+        got: &'static str,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidConversion(spec) => write!(
+                f,
+                "invalid conversion '%{}' to 'putf'",
+                String::from_utf8_lossy(spec)
+            ),
+            Error::QuotedWithModifiers => write!(f, "specifier '%q' cannot have modifiers"),
+            Error::BadArgument { arg, func, msg } => {
+                write!(f, "bad argument #{} to '{}' ({})", arg, func, msg)
+            }
+            Error::WrongType {
+                arg,
+                func,
+                expected,
+                got,
+            } => write!(
+                f,
+                "bad argument #{} to '{}' ({} expected, got {})",
+                arg, func, expected, got
+            ),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Flags, width and precision are each limited like `MAX_FORMAT` limits
+/// them in the reference
+const MAX_DIGITS: usize = 2;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Buffer {
+    data: Vec<u8>,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `buf:put(...)`: append strings, and numbers as `tostring` shows them
+    pub fn put(&mut self, values: &[Scalar]) -> Result<&mut Self> {
+        for (i, value) in values.iter().enumerate() {
+            let s = value.to_str().ok_or(Error::WrongType {
+                arg: i + 1,
+                func: "put",
+                expected: "string",
+                got: value.type_name(),
+            })?;
+            self.data.extend_from_slice(&s);
+        }
+        Ok(self)
+    }
+
+    /// `buf:putf(format, ...)`: append `args` formatted like
+    /// `string.format`
+    pub fn putf(&mut self, format: &[u8], args: &[Scalar]) -> Result<&mut Self> {
+        let out = &mut self.data;
+        let mut args = args.iter();
+        // The format is argument 1
+        let mut arg = 1;
+        let mut i = 0;
+        while i < format.len() {
+            if format[i] != b'%' {
+                out.push(format[i]);
+                i += 1;
+                continue;
+            }
+            if format.get(i + 1) == Some(&b'%') {
+                out.push(b'%');
+                i += 2;
+                continue;
+            }
+            let (spec, len) = Spec::parse(&format[i + 1..])?;
+            i += 1 + len;
+            arg += 1;
+            let value = args.next().ok_or(Error::BadArgument {
+                arg,
+                func: "putf",
+                msg: "no value",
+            })?;
+            match spec.conversion {
+                b'c' => {
+                    let c = check_integer(value, arg)? as u8;
+                    spec.pad(out, "", "", &[c], false);
+                }
+                b'd' | b'i' => {
+                    let n = check_integer(value, arg)?;
+                    let digits = spec.int_digits(n.unsigned_abs().to_string());
+                    spec.pad(out, spec.sign(n < 0), "", digits.as_bytes(), true);
+                }
+                b'u' | b'o' | b'x' | b'X' => {
+                    let n = check_integer(value, arg)? as u64;
+                    let digits = match spec.conversion {
+                        b'u' => n.to_string(),
+                        b'o' => format!("{:o}", n),
+                        b'x' => format!("{:x}", n),
+                        _ => format!("{:X}", n),
+                    };
+                    let mut digits = spec.int_digits(digits);
+                    let mut prefix = "";
+                    if spec.alt && spec.conversion == b'o' && !digits.starts_with('0') {
+                        digits.insert(0, '0');
+                    } else if spec.alt && n != 0 && spec.conversion != b'o' {
+                        prefix = if spec.conversion == b'x' { "0x" } else { "0X" };
+                    }
+                    spec.pad(out, "", prefix, digits.as_bytes(), true);
+                }
+                b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                    let f = check_number(value, arg)?.to_float();
+                    spec.float(out, f);
+                }
+                b'q' => quote(out, value),
+                b's' => {
+                    let s = value.to_display();
+                    let s = match spec.precision {
+                        Some(p) if p < s.len() => &s[..p],
+                        _ => &s[..],
+                    };
+                    spec.pad(out, "", "", s, false);
+                }
+                _ => unreachable!(),
+            }
+        }
+        Ok(self)
+    }
+
+    /// `buf:tostring()`
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// `buf:reset()`, emptying the buffer but keeping its memory
+    pub fn reset(&mut self) -> &mut Self {
+        self.data.clear();
+        self
+    }
+}
+
+/// A parsed `%` directive
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: u8,
+}
+
+impl Spec {
+    /// Parse the directive at the start of `s`, just after its `%`, and
+    /// return it with its length
+    fn parse(s: &[u8]) -> Result<(Spec, usize)> {
+        let invalid = |end: usize| {
+            let end = (end + 1).min(s.len());
+            Error::InvalidConversion(s[..end].to_vec())
+        };
+        let mut spec = Spec::default();
+        let mut i = 0;
+        while let Some(&c) = s.get(i) {
+            match c {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let flags = &s[..i];
+        let digits = |i: &mut usize| {
+            let start = *i;
+            while s.get(*i).is_some_and(u8::is_ascii_digit) {
+                *i += 1;
+            }
+            (*i - start <= MAX_DIGITS).then(|| {
+                s[start..*i]
+                    .iter()
+                    .fold(0, |n, d| n * 10 + (d - b'0') as usize)
+            })
+        };
+        spec.width = digits(&mut i).ok_or_else(|| invalid(i))?;
+        if s.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(digits(&mut i).ok_or_else(|| invalid(i))?);
+        }
+        let Some(&conversion) = s.get(i) else {
+            return Err(invalid(i));
+        };
+        spec.conversion = conversion;
+        // The flags each conversion takes, and whether it takes a precision
+        let (allowed, precision): (&[u8], bool) = match conversion {
+            b'c' => (b"-", false),
+            b'd' | b'i' => (b"-+ 0", true),
+            b'u' => (b"-0", true),
+            b'o' | b'x' | b'X' => (b"-#0", true),
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => (b"-+ #0", true),
+            b's' => (b"-", true),
+            b'q' => (b"", false),
+            _ => return Err(invalid(i)),
+        };
+        let modified = !flags.is_empty() || spec.width > 0 || spec.precision.is_some();
+        if conversion == b'q' && modified {
+            return Err(Error::QuotedWithModifiers);
+        }
+        if !flags.iter().all(|f| allowed.contains(f)) || (spec.precision.is_some() && !precision) {
+            return Err(invalid(i));
+        }
+        Ok((spec, i + 1))
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    /// Apply the precision of an integer directive, the least number of
+    /// digits to write
+    fn int_digits(&self, digits: String) -> String {
+        match self.precision {
+            Some(0) if digits == "0" => String::new(),
+            Some(p) if p > digits.len() => format!("{}{}", "0".repeat(p - digits.len()), digits),
+            _ => digits,
+        }
+    }
+
+    /// Write `sign`, `prefix` and `body` padded out to the width. Numbers
+    /// are padded with zeros after the prefix when the `0` flag asks.
+    fn pad(&self, out: &mut Vec<u8>, sign: &str, prefix: &str, body: &[u8], numeric: bool) {
+        let len = sign.len() + prefix.len() + body.len();
+        let fill = self.width.saturating_sub(len);
+        // A precision on an integer turns off zero padding, as in C
+        let int = matches!(self.conversion, b'd' | b'i' | b'u' | b'o' | b'x' | b'X');
+        let zeros = numeric && self.zero && !self.left && !(int && self.precision.is_some());
+        if !self.left && !zeros {
+            out.resize(out.len() + fill, b' ');
+        }
+        out.extend_from_slice(sign.as_bytes());
+        out.extend_from_slice(prefix.as_bytes());
+        if zeros {
+            out.resize(out.len() + fill, b'0');
+        }
+        out.extend_from_slice(body);
+        if self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+    }
+
+    fn float(&self, out: &mut Vec<u8>, f: f64) {
+        let upper = self.conversion.is_ascii_uppercase();
+        let sign = self.sign(f.is_sign_negative());
+        let f = f.abs();
+        if !f.is_finite() {
+            let body = match (f.is_nan(), upper) {
+                (true, false) => "nan",
+                (true, true) => "NAN",
+                (false, false) => "inf",
+                (false, true) => "INF",
+            };
+            return self.pad(out, sign, "", body.as_bytes(), false);
+        }
+        let mut prefix = "";
+        let body = match self.conversion.to_ascii_lowercase() {
+            b'a' => {
+                prefix = if upper { "0X" } else { "0x" };
+                hex_float(f, self.precision, self.alt)
+            }
+            b'e' => exp_form(f, self.precision.unwrap_or(6), self.alt),
+            b'f' => {
+                let precision = self.precision.unwrap_or(6);
+                let point = if self.alt && precision == 0 { "." } else { "" };
+                format!("{:.*}{}", precision, f, point)
+            }
+            _ => general_form(f, self.precision.unwrap_or(6), self.alt),
+        };
+        let body = if upper {
+            body.to_ascii_uppercase()
+        } else {
+            body
+        };
+        self.pad(out, sign, prefix, body.as_bytes(), true);
+    }
+}
+
+/// C's `%.{precision}e` of a non-negative finite number
+fn exp_form(f: f64, precision: usize, alt: bool) -> String {
+    let sci = format!("{:.*e}", precision, f);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let point = if alt && precision == 0 { "." } else { "" };
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{}{}e{}{:02}", mantissa, point, sign, exp.abs())
+}
+
+/// C's `%.{precision}g` of a non-negative finite number
+fn general_form(f: f64, precision: usize, alt: bool) -> String {
+    let precision = precision.max(1);
+    let exp = if f == 0.0 {
+        0
+    } else {
+        // The exponent after rounding to `precision` significant digits
+        let sci = format!("{:.*e}", precision - 1, f);
+        sci.split_once('e').unwrap().1.parse().unwrap()
+    };
+    let s = if exp < -4 || exp >= precision as i32 {
+        exp_form(f, precision - 1, alt)
+    } else {
+        let decimals = (precision as i32 - 1 - exp) as usize;
+        let point = if alt && decimals == 0 { "." } else { "" };
+        format!("{:.*}{}", decimals, f, point)
+    };
+    if alt {
+        return s;
+    }
+    // Without `#`, trailing zeros in the fraction go
+    let (mantissa, exp) = match s.find('e') {
+        Some(e) => s.split_at(e),
+        None => (&s[..], ""),
+    };
+    let mantissa = match mantissa.contains('.') {
+        true => mantissa.trim_end_matches('0').trim_end_matches('.'),
+        false => mantissa,
+    };
+    format!("{}{}", mantissa, exp)
+}
+
+/// C's `%a` of a non-negative finite number, without the `0x`
+fn hex_float(f: f64, precision: Option<usize>, alt: bool) -> String {
+    let bits = f.to_bits();
+    let biased = (bits >> 52) as i64 & 0x7ff;
+    let fraction = bits & ((1 << 52) - 1);
+    let (lead, exp) = match (biased, fraction) {
+        (0, 0) => (0, 0),
+        (0, _) => (0, -1022),
+        _ => (1, biased - 1023),
+    };
+    let digits = match precision {
+        // Round to the nearest, ties to even, as glibc does
+        Some(p) if p < 13 => {
+            let shift = (13 - p) * 4;
+            let full = (lead << 52) | fraction;
+            let rest = full & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            let mut kept = full >> shift;
+            if rest > half || (rest == half && kept & 1 == 1) {
+                kept += 1;
+            }
+            let lead = kept >> (p * 4);
+            let fraction = kept & ((1 << (p * 4)) - 1);
+            let digits = match p {
+                0 => String::new(),
+                _ => format!("{:0w$x}", fraction, w = p),
+            };
+            return hex_parts(lead, &digits, exp, alt);
+        }
+        Some(p) => format!("{:013x}{}", fraction, "0".repeat(p - 13)),
+        None => format!("{:013x}", fraction)
+            .trim_end_matches('0')
+            .to_string(),
+    };
+    hex_parts(lead, &digits, exp, alt)
+}
+
+fn hex_parts(lead: u64, digits: &str, exp: i64, alt: bool) -> String {
+    let point = if digits.is_empty() && !alt { "" } else { "." };
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{:x}{}{}p{}{}", lead, point, digits, sign, exp.abs())
+}
+
+/// `%q`: a value written so Lua reads it back the same
+fn quote(out: &mut Vec<u8>, value: &Scalar) {
+    match value {
+        Scalar::String(s) => value::quote(out, s),
+        Scalar::Nil | Scalar::Boolean(_) => out.extend_from_slice(&value.to_display()),
+        // The smallest integer reads back as a float when written in decimal
+        Scalar::Number(Number::Integer(i64::MIN)) => out.extend_from_slice(b"0x8000000000000000"),
+        Scalar::Number(Number::Integer(n)) => out.extend_from_slice(n.to_string().as_bytes()),
+        Scalar::Number(Number::Float(f)) => {
+            let text = if f.is_nan() {
+                "(0/0)".to_string()
+            } else if f.is_infinite() {
+                if *f < 0.0 { "-1e9999" } else { "1e9999" }.to_string()
+            } else {
+                // Hexadecimal keeps every bit
+                let sign = if f.is_sign_negative() { "-" } else { "" };
+                format!("{}0x{}", sign, hex_float(f.abs(), None, false))
+            };
+            out.extend_from_slice(text.as_bytes());
+        }
+    }
+}
+
+fn check_integer(value: &Scalar, arg: usize) -> Result<i64> {
+    match check_number(value, arg)? {
+        Number::Integer(n) => Ok(n),
+        Number::Float(f) => number::float_to_integer(f).ok_or(Error::BadArgument {
+            arg,
+            func: "putf",
+            msg: "number has no integer representation",
+        }),
+    }
+}
+
+fn check_number(value: &Scalar, arg: usize) -> Result<Number> {
+    value.to_number().ok_or(Error::WrongType {
+        arg,
+        func: "putf",
+        expected: "number",
+        got: value.type_name(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> Scalar {
+        Scalar::Number(Number::Integer(n))
+    }
+
+    fn float(f: f64) -> Scalar {
+        Scalar::Number(Number::Float(f))
+    }
+
+    fn string(s: &str) -> Scalar {
+        Scalar::String(s.as_bytes().to_vec())
+    }
+
+    fn format(format: &str, args: &[Scalar]) -> String {
+        let mut buffer = Buffer::new();
+        buffer.putf(format.as_bytes(), args).unwrap();
+        String::from_utf8(buffer.as_bytes().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn put_and_reset() {
+        let mut buffer = Buffer::new();
+        buffer
+            .put(&[string("a"), int(1), float(2.0)])
+            .unwrap()
+            .put(&[float(0.5)])
+            .unwrap();
+        assert_eq!(buffer.as_bytes(), b"a12.00.5");
+        assert_eq!(buffer.len(), 8);
+        buffer.reset().put(&[string("b")]).unwrap();
+        assert_eq!(buffer.as_bytes(), b"b");
+        assert!(!buffer.is_empty());
+        assert_eq!(
+            buffer
+                .put(&[string("c"), Scalar::Nil])
+                .map(|_| ())
+                .unwrap_err()
+                .to_string(),
+            "bad argument #2 to 'put' (string expected, got nil)"
+        );
+    }
+
+    #[test]
+    fn putf_integers() {
+        assert_eq!(
+            format("%d|%5d|%-5d|%05d", &[int(42), int(-42), int(7), int(-7)]),
+            "42|  -42|7    |-0007"
+        );
+        assert_eq!(
+            format("%+d % d %.3d %.0d", &[int(5), int(5), int(5), int(0)]),
+            "+5  5 005 "
+        );
+        assert_eq!(
+            format(
+                "%x %X %#x %o %#o",
+                &[int(255), int(255), int(255), int(8), int(8)]
+            ),
+            "ff FF 0xff 10 010"
+        );
+        assert_eq!(format("%x", &[int(-1)]), "ffffffffffffffff");
+        assert_eq!(format("%c%c", &[int(72), int(105)]), "Hi");
+        assert_eq!(format("%d %d", &[float(3.0), string("0x10")]), "3 16");
+        assert_eq!(format("100%%", &[]), "100%");
+    }
+
+    #[test]
+    fn putf_floats() {
+        assert_eq!(
+            format(
+                "%f %.2f %10.3f %-8.1f|",
+                &[float(1.5), float(1.23456), float(-2.5), float(0.25)]
+            ),
+            "1.500000 1.23     -2.500 0.2     |"
+        );
+        assert_eq!(
+            format(
+                "%e %.2E %#.0e",
+                &[float(12345.678), float(0.000123), float(5.0)]
+            ),
+            "1.234568e+04 1.23E-04 5.e+00"
+        );
+        assert_eq!(
+            format(
+                "%g %g %g %g",
+                &[float(100000.0), float(1e6), float(0.0001), float(1e-5)]
+            ),
+            "100000 1e+06 0.0001 1e-05"
+        );
+        assert_eq!(
+            format("%.3g %#g %G", &[float(1.23456), float(1.0), float(1e-10)]),
+            "1.23 1.00000 1E-10"
+        );
+        assert_eq!(
+            format(
+                "%5.1f %+.1f %05.1f",
+                &[float(f64::INFINITY), float(-0.0), float(-1.5)]
+            ),
+            "  inf -0.0 -01.5"
+        );
+        assert_eq!(
+            format(
+                "%a %A %.1a %a",
+                &[float(1.0), float(0.5), float(1.96875), float(0.0)]
+            ),
+            "0x1p+0 0X1P-1 0x2.0p+0 0x0p+0"
+        );
+        assert_eq!(
+            format("%.0a %#.0a %.0a", &[float(1.0), float(1.0), float(1.5)]),
+            "0x1p+0 0x1.p+0 0x2p+0"
+        );
+        assert_eq!(
+            format("%a", &[float(f64::MIN_POSITIVE / 2.0)]),
+            "0x0.8p-1022"
+        );
+        assert_eq!(
+            format("%.0f %5.2s|", &[int(2), string("hello")]),
+            "2    he|"
+        );
+    }
+
+    #[test]
+    fn putf_quoted() {
+        assert_eq!(
+            format("%q", &[string("a\"b\\\n\r1\x001")]),
+            "\"a\\\"b\\\\\\\n\\0131\\0001\""
+        );
+        assert_eq!(
+            format("%q %q %q", &[int(i64::MIN), int(-3), float(0.5)]),
+            "0x8000000000000000 -3 0x1p-1"
+        );
+        assert_eq!(
+            format(
+                "%q %q %q",
+                &[
+                    float(f64::INFINITY),
+                    float(f64::NEG_INFINITY),
+                    float(f64::NAN)
+                ]
+            ),
+            "1e9999 -1e9999 (0/0)"
+        );
+        assert_eq!(
+            format(
+                "%q %q %s %s",
+                &[
+                    Scalar::Nil,
+                    Scalar::Boolean(true),
+                    Scalar::Nil,
+                    Scalar::Boolean(false)
+                ]
+            ),
+            "nil true nil false"
+        );
+    }
+
+    #[test]
+    fn putf_errors() {
+        let error = |format: &str, args: &[Scalar]| {
+            Buffer::new()
+                .putf(format.as_bytes(), args)
+                .map(|_| ())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("%y", &[int(1)]), "invalid conversion '%y' to 'putf'");
+        assert_eq!(
+            error("%123d", &[int(1)]),
+            "invalid conversion '%123d' to 'putf'"
+        );
+        assert_eq!(
+            error("%#d", &[int(1)]),
+            "invalid conversion '%#d' to 'putf'"
+        );
+        assert_eq!(
+            error("%.3c", &[int(1)]),
+            "invalid conversion '%.3c' to 'putf'"
+        );
+        assert_eq!(error("%", &[]), "invalid conversion '%' to 'putf'");
+        assert_eq!(
+            error("%10q", &[int(1)]),
+            "specifier '%q' cannot have modifiers"
+        );
+        assert_eq!(
+            error("%d %d", &[int(1)]),
+            "bad argument #3 to 'putf' (no value)"
+        );
+        assert_eq!(
+            error("%d", &[float(1.5)]),
+            "bad argument #2 to 'putf' (number has no integer representation)"
+        );
+        assert_eq!(
+            error("%f", &[string("x")]),
+            "bad argument #2 to 'putf' (number expected, got string)"
+        );
+        assert_eq!(
+            error("%x", &[Scalar::Boolean(true)]),
+            "bad argument #2 to 'putf' (number expected, got boolean)"
+        );
+    }
+}
@@ -0,0 +1,594 @@
+//! Calendar conversions and formatting for `os.date` and `os.time`.
+//!
+//! Times are seconds since the Unix epoch. Local time comes from the C
+//! library's `localtime_r`, and is the same as UTC where there isn't one.
+
+/// The fields of a `*t` date table. `wday` and `yday` are 1-based like in
+/// Lua, with Sunday as day 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub min: i64,
+    pub sec: i64,
+    pub wday: i64,
+    pub yday: i64,
+    pub isdst: bool,
+}
+
+/// The result of `os.date`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Date {
+    Table(DateTime),
+    String(Vec<u8>),
+}
+
+/// A UTC offset in effect at some time, for local dates and `%z`/`%Z`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    /// Seconds east of UTC
+    pub offset: i64,
+    pub name: String,
+    pub isdst: bool,
+}
+
+impl Zone {
+    pub fn utc() -> Self {
+        Self {
+            offset: 0,
+            name: "UTC".to_string(),
+            isdst: false,
+        }
+    }
+
+    /// The local zone at `time`, as the C library sees it. Only targets
+    /// with a 64-bit `time_t` and a `struct tm` ending in `tm_gmtoff` and
+    /// `tm_zone` ask it; everywhere else local time is UTC.
+    #[cfg(all(
+        any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly"
+        ),
+        target_pointer_width = "64"
+    ))]
+    pub fn local(time: i64) -> Self {
+        // The glibc, musl and BSD layout: the nine C99 fields, then the
+        // offset and zone name
+        #[repr(C)]
+        struct Tm {
+            fields: [std::ffi::c_int; 9],
+            gmtoff: i64,
+            zone: *const std::ffi::c_char,
+        }
+        unsafe extern "C" {
+            fn localtime_r(time: *const i64, tm: *mut Tm) -> *mut Tm;
+        }
+        let mut tm = std::mem::MaybeUninit::<Tm>::uninit();
+        // SAFETY: both pointers are valid, and localtime_r fills in `tm`
+        // when it doesn't return null
+        let tm = unsafe {
+            if localtime_r(&time, tm.as_mut_ptr()).is_null() {
+                return Self::utc();
+            }
+            tm.assume_init()
+        };
+        let name = if tm.zone.is_null() {
+            String::new()
+        } else {
+            // SAFETY: tm_zone points at a C string that outlives the call
+            unsafe { std::ffi::CStr::from_ptr(tm.zone) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        Self {
+            offset: tm.gmtoff,
+            name,
+            isdst: tm.fields[8] > 0,
+        }
+    }
+
+    #[cfg(not(all(
+        any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly"
+        ),
+        target_pointer_width = "64"
+    )))]
+    pub fn local(_time: i64) -> Self {
+        Self::utc()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A `%` directive that isn't in the C99 set, holding the rest of the
+    /// format after the `%`
+    InvalidConversion(Vec<u8>),
+    /// A date table field that doesn't fit in a C `int`
+    FieldOutOfBound(&'static str),
+    /// A time whose date has a year outside the C `int` range
+    DateNotRepresentable,
+    TimeNotRepresentable,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidConversion(conv) => write!(
+                f,
+                "bad argument #1 to 'date' (invalid conversion specifier '%{}')",
+                String::from_utf8_lossy(conv)
+            ),
+            Error::FieldOutOfBound(field) => write!(f, "field '{}' is out-of-bound", field),
+            Error::DateNotRepresentable => {
+                write!(f, "date result cannot be represented in this installation")
+            }
+            Error::TimeNotRepresentable => {
+                write!(f, "time result cannot be represented in this installation")
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+const DAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Days since the epoch of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The year, month and day of a count of days since the epoch
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Break a time down into its date fields, like `gmtime`
+pub fn fields(time: i64) -> Result<DateTime> {
+    let days = time.div_euclid(86400);
+    let secs = time.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    if year - 1900 > i32::MAX as i64 || year - 1900 < i32::MIN as i64 {
+        return Err(Error::DateNotRepresentable);
+    }
+    Ok(DateTime {
+        year,
+        month,
+        day,
+        hour: secs / 3600,
+        min: secs / 60 % 60,
+        sec: secs % 60,
+        wday: (days + 4).rem_euclid(7) + 1,
+        yday: days - days_from_civil(year, 1, 1) + 1,
+        isdst: false,
+    })
+}
+
+/// The date fields of a time in `zone`
+pub fn fields_in(time: i64, zone: &Zone) -> Result<DateTime> {
+    let local = time
+        .checked_add(zone.offset)
+        .ok_or(Error::DateNotRepresentable)?;
+    Ok(DateTime {
+        isdst: zone.isdst,
+        ..fields(local)?
+    })
+}
+
+/// Convert local date fields to a time, like `mktime`. Out-of-range fields
+/// are normalized (`month = 14` is February of the next year) and `date` is
+/// updated to the normalized fields, `wday` and `yday` included.
+pub fn time(date: &mut DateTime) -> Result<i64> {
+    let guess = timegm(date)?;
+    // The offset in effect at the local time, found from the offset at the
+    // same wall clock reading in UTC
+    let offset = Zone::local(guess - Zone::local(guess).offset).offset;
+    let time = guess - offset;
+    *date = fields_in(time, &Zone::local(time)).map_err(|_| Error::TimeNotRepresentable)?;
+    Ok(time)
+}
+
+/// `time` for fields in UTC, like `timegm`
+pub fn timegm(date: &mut DateTime) -> Result<i64> {
+    check_fields(&[
+        ("year", date.year, 1900),
+        ("month", date.month, 1),
+        ("day", date.day, 0),
+        ("hour", date.hour, 0),
+        ("min", date.min, 0),
+        ("sec", date.sec, 0),
+    ])?;
+    // Every field fits in 32 bits, so none of this can overflow
+    let months = date.year * 12 + date.month - 1;
+    let days = days_from_civil(months.div_euclid(12), months.rem_euclid(12) + 1, 1);
+    let days = days + date.day - 1;
+    let time = days * 86400 + date.hour * 3600 + date.min * 60 + date.sec;
+    *date = fields(time).map_err(|_| Error::TimeNotRepresentable)?;
+    Ok(time)
+}
+
+/// `os.date(format, time)` in local time. A leading `!` asks for UTC and a
+/// format of exactly `*t` gives the fields as a table.
+pub fn date(format: &[u8], time: i64) -> Result<Date> {
+    let (format, zone) = match format.strip_prefix(b"!") {
+        Some(format) => (format, Zone::utc()),
+        None => (format, Zone::local(time)),
+    };
+    let date = fields_in(time, &zone)?;
+    if format == b"*t" {
+        return Ok(Date::Table(date));
+    }
+    strftime(format, &date, &zone).map(Date::String)
+}
+
+/// Check a directive after `%` and return its length, 2 for the `E` and
+/// `O` modified forms
+fn conversion(spec: &[u8]) -> Option<usize> {
+    match spec {
+        [b'E', b'c' | b'C' | b'x' | b'X' | b'y' | b'Y', ..] => Some(2),
+        [
+            b'O',
+            b'd' | b'e' | b'H' | b'I' | b'm' | b'M' | b'S' | b'u' | b'U' | b'V' | b'w' | b'W'
+            | b'y',
+            ..,
+        ] => Some(2),
+        [c, ..] if b"aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%".contains(c) => Some(1),
+        _ => None,
+    }
+}
+
+/// The ISO 8601 week-based year and week number
+fn iso_week(date: &DateTime) -> (i64, i64) {
+    let weekday = (date.wday + 5) % 7 + 1;
+    let week = (date.yday - weekday + 10) / 7;
+    let weeks_in = |year: i64| {
+        let p = |y: i64| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)) % 7;
+        if p(year).rem_euclid(7) == 4 || p(year - 1).rem_euclid(7) == 3 {
+            53
+        } else {
+            52
+        }
+    };
+    if week < 1 {
+        (date.year - 1, weeks_in(date.year - 1))
+    } else if week > weeks_in(date.year) {
+        (date.year + 1, 1)
+    } else {
+        (date.year, week)
+    }
+}
+
+/// Check that each field, less its offset, fits in a C `int` the way the
+/// fields of a `struct tm` do
+fn check_fields(fields: &[(&'static str, i64, i64)]) -> Result<()> {
+    for &(name, value, delta) in fields {
+        let fits = if value >= 0 {
+            value - delta <= i32::MAX as i64
+        } else {
+            i32::MIN as i64 + delta <= value
+        };
+        if !fits {
+            return Err(Error::FieldOutOfBound(name));
+        }
+    }
+    Ok(())
+}
+
+/// The name of a 1-based `wday` or `month`
+fn name(names: &[&'static str], n: i64, field: &'static str) -> Result<&'static str> {
+    usize::try_from(n - 1)
+        .ok()
+        .and_then(|i| names.get(i).copied())
+        .ok_or(Error::FieldOutOfBound(field))
+}
+
+/// Format date fields with the C99 `strftime` directives in the C locale,
+/// taking `%z` and `%Z` from `zone`. Fields don't need to be normalized,
+/// but a name directive fails on a `wday` or `month` with no name.
+pub fn strftime(format: &[u8], date: &DateTime, zone: &Zone) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    check_fields(&[
+        ("year", date.year, 1900),
+        ("month", date.month, 1),
+        ("day", date.day, 0),
+        ("hour", date.hour, 0),
+        ("min", date.min, 0),
+        ("sec", date.sec, 0),
+        ("wday", date.wday, 1),
+        ("yday", date.yday, 1),
+    ])?;
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < format.len() {
+        if format[i] != b'%' {
+            out.push(format[i]);
+            i += 1;
+            continue;
+        }
+        let spec = &format[i + 1..];
+        let len = conversion(spec).ok_or_else(|| Error::InvalidConversion(spec.to_vec()))?;
+        i += 1 + len;
+        let directive = spec[len - 1];
+        let day_name = match directive {
+            b'a' | b'A' | b'c' => name(&DAY_NAMES, date.wday, "wday")?,
+            _ => "",
+        };
+        let month_name = match directive {
+            b'b' | b'h' | b'B' | b'c' => name(&MONTH_NAMES, date.month, "month")?,
+            _ => "",
+        };
+        let hour12 = (date.hour + 11).rem_euclid(12) + 1;
+        let result = match directive {
+            b'a' => write!(out, "{}", &day_name[..3]),
+            b'A' => write!(out, "{}", day_name),
+            b'b' | b'h' => write!(out, "{}", &month_name[..3]),
+            b'B' => write!(out, "{}", month_name),
+            b'c' => write!(
+                out,
+                "{} {} {:2} {:02}:{:02}:{:02} {}",
+                &day_name[..3],
+                &month_name[..3],
+                date.day,
+                date.hour,
+                date.min,
+                date.sec,
+                date.year
+            ),
+            b'C' => write!(out, "{:02}", date.year.div_euclid(100)),
+            b'd' => write!(out, "{:02}", date.day),
+            b'D' | b'x' => write!(
+                out,
+                "{:02}/{:02}/{:02}",
+                date.month,
+                date.day,
+                date.year.rem_euclid(100)
+            ),
+            b'e' => write!(out, "{:2}", date.day),
+            b'F' => write!(out, "{}-{:02}-{:02}", date.year, date.month, date.day),
+            b'g' => write!(out, "{:02}", iso_week(date).0.rem_euclid(100)),
+            b'G' => write!(out, "{}", iso_week(date).0),
+            b'H' => write!(out, "{:02}", date.hour),
+            b'I' => write!(out, "{:02}", hour12),
+            b'j' => write!(out, "{:03}", date.yday),
+            b'm' => write!(out, "{:02}", date.month),
+            b'M' => write!(out, "{:02}", date.min),
+            b'n' => writeln!(out),
+            b'p' => write!(out, "{}", if date.hour < 12 { "AM" } else { "PM" }),
+            b'r' => write!(
+                out,
+                "{:02}:{:02}:{:02} {}",
+                hour12,
+                date.min,
+                date.sec,
+                if date.hour < 12 { "AM" } else { "PM" }
+            ),
+            b'R' => write!(out, "{:02}:{:02}", date.hour, date.min),
+            b'S' => write!(out, "{:02}", date.sec),
+            b't' => write!(out, "\t"),
+            b'T' | b'X' => write!(out, "{:02}:{:02}:{:02}", date.hour, date.min, date.sec),
+            b'u' => write!(out, "{}", (date.wday + 5) % 7 + 1),
+            b'U' => write!(out, "{:02}", (date.yday + 6 - (date.wday - 1)) / 7),
+            b'V' => write!(out, "{:02}", iso_week(date).1),
+            b'w' => write!(out, "{}", date.wday - 1),
+            b'W' => write!(out, "{:02}", (date.yday + 6 - (date.wday + 5) % 7) / 7),
+            b'y' => write!(out, "{:02}", date.year.rem_euclid(100)),
+            b'Y' => write!(out, "{}", date.year),
+            b'z' => write!(
+                out,
+                "{}{:02}{:02}",
+                if zone.offset < 0 { '-' } else { '+' },
+                zone.offset.abs() / 3600,
+                zone.offset.abs() / 60 % 60
+            ),
+            b'Z' => write!(out, "{}", zone.name),
+            b'%' => write!(out, "%"),
+            _ => unreachable!(),
+        };
+        result.expect("writing to a Vec can't fail");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `date` in UTC
+    fn format(format: &str, time: i64) -> String {
+        match date(&[b"!", format.as_bytes()].concat(), time).unwrap() {
+            Date::String(s) => String::from_utf8(s).unwrap(),
+            Date::Table(_) => panic!("expected a string"),
+        }
+    }
+
+    #[test]
+    fn fields_of_times() {
+        let epoch = fields(0).unwrap();
+        assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+        assert_eq!((epoch.wday, epoch.yday), (5, 1));
+
+        // 2000-02-29 23:59:59, a leap day
+        let leap = fields(951868799).unwrap();
+        assert_eq!((leap.year, leap.month, leap.day), (2000, 2, 29));
+        assert_eq!((leap.hour, leap.min, leap.sec), (23, 59, 59));
+        assert_eq!((leap.wday, leap.yday), (3, 60));
+
+        let before = fields(-1).unwrap();
+        assert_eq!((before.year, before.month, before.day), (1969, 12, 31));
+        assert_eq!(before.hour, 23);
+
+        assert_eq!(date(b"!*t", 0), Ok(Date::Table(epoch)));
+        assert_eq!(fields(i64::MAX), Err(Error::DateNotRepresentable));
+    }
+
+    #[test]
+    fn time_normalizes_fields() {
+        let mut date = DateTime {
+            year: 2000,
+            month: 1,
+            day: 1,
+            hour: 12,
+            min: 0,
+            sec: 0,
+            wday: 0,
+            yday: 0,
+            isdst: false,
+        };
+        assert_eq!(timegm(&mut date), Ok(946728000));
+        assert_eq!((date.wday, date.yday), (7, 1));
+
+        date.month = 14;
+        date.day = 0;
+        date.sec = -1;
+        timegm(&mut date).unwrap();
+        assert_eq!((date.year, date.month, date.day), (2001, 1, 31));
+        assert_eq!((date.hour, date.min, date.sec), (11, 59, 59));
+
+        date.year = 1 << 40;
+        assert_eq!(timegm(&mut date), Err(Error::FieldOutOfBound("year")));
+    }
+
+    #[test]
+    fn local_dates() {
+        let t = 998575502;
+        let zone = Zone::local(t);
+        let local = fields_in(t, &zone).unwrap();
+        assert_eq!(date(b"*t", t), Ok(Date::Table(local)));
+        let mut round_trip = local;
+        assert_eq!(time(&mut round_trip), Ok(t));
+        assert_eq!(round_trip, local);
+        assert_eq!(
+            date(b"%Z", t),
+            Ok(Date::String(zone.name.clone().into_bytes()))
+        );
+        // Only an exact `*t` asks for a table
+        assert_eq!(date(b"!*tx", t), Ok(Date::String(b"*tx".to_vec())));
+
+        let zone = Zone {
+            offset: -(4 * 3600 + 30 * 60),
+            name: "VET".to_string(),
+            isdst: false,
+        };
+        let date = fields_in(t, &zone).unwrap();
+        assert_eq!((date.hour, date.min), (9, 35));
+        assert_eq!(
+            strftime(b"%H:%M %z %Z", &date, &zone),
+            Ok(b"09:35 -0430 VET".to_vec())
+        );
+    }
+
+    #[test]
+    fn strftime_directives() {
+        // 2001-08-23 14:05:02, a Thursday
+        let t = 998575502;
+        assert_eq!(format("%c", t), "Thu Aug 23 14:05:02 2001");
+        assert_eq!(format("%a %A %b %B %h", t), "Thu Thursday Aug August Aug");
+        assert_eq!(
+            format("%C %y %Y %D %F", t),
+            "20 01 2001 08/23/01 2001-08-23"
+        );
+        assert_eq!(format("%d|%e|%j|%m", t), "23|23|235|08");
+        assert_eq!(format("%H %I %M %S %p", t), "14 02 05 02 PM");
+        assert_eq!(
+            format("%r|%R|%T|%x|%X", t),
+            "02:05:02 PM|14:05|14:05:02|08/23/01|14:05:02"
+        );
+        assert_eq!(format("%u %w %U %W %V %G %g", t), "4 4 33 34 34 2001 01");
+        assert_eq!(format("%z %Z %% %n%t", t), "+0000 UTC % \n\t");
+        assert_eq!(
+            format("%Ec %EY %Od %OH", t),
+            "Thu Aug 23 14:05:02 2001 2001 23 14"
+        );
+
+        // 2005-01-01 is a Saturday in ISO week 53 of 2004
+        assert_eq!(format("%G-W%V-%u %U %W", 1104537600), "2004-W53-6 00 00");
+        // 2008-12-29 is a Monday in ISO week 1 of 2009
+        assert_eq!(format("%G-W%V-%u", 1230508800), "2009-W01-1");
+    }
+
+    #[test]
+    fn strftime_out_of_range_fields() {
+        let mut date = fields(0).unwrap();
+        date.wday = 0;
+        date.month = 13;
+        let utc = Zone::utc();
+        assert_eq!(
+            strftime(b"%Y-%m-%d %H", &date, &utc),
+            Ok(b"1970-13-01 00".to_vec())
+        );
+        assert_eq!(
+            strftime(b"%a", &date, &utc),
+            Err(Error::FieldOutOfBound("wday"))
+        );
+        assert_eq!(
+            strftime(b"%B", &date, &utc),
+            Err(Error::FieldOutOfBound("month"))
+        );
+        date.hour = i64::MIN;
+        assert_eq!(
+            strftime(b"", &date, &utc),
+            Err(Error::FieldOutOfBound("hour"))
+        );
+    }
+
+    #[test]
+    fn invalid_conversions() {
+        assert_eq!(
+            date(b"%Ez abc", 0).unwrap_err().to_string(),
+            "bad argument #1 to 'date' (invalid conversion specifier '%Ez abc')"
+        );
+        assert_eq!(date(b"%Q", 0), Err(Error::InvalidConversion(b"Q".to_vec())));
+        assert_eq!(date(b"50%", 0), Err(Error::InvalidConversion(vec![])));
+    }
+}
@@ -0,0 +1,370 @@
+//! An `fs` library for what `io` can't do: list directories, stat files,
+//! make and remove directories, and work with paths.
+//!
+//! Paths use `/` as the separator, whatever the platform.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// An operating system error, with the path it was about
+    Io { path: String, msg: String },
+    /// A write through an `Fs` that is read-only, with the function name
+    ReadOnly(&'static str),
+    /// Any call through an `Fs` with no access, with the function name
+    NoAccess(&'static str),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { path, msg } => write!(f, "{}: {}", path, msg),
+            Error::ReadOnly(func) => write!(f, "'{}' not allowed (read-only filesystem)", func),
+            Error::NoAccess(func) => write!(f, "'{}' not allowed (no filesystem access)", func),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |e| {
+        let msg = e.to_string();
+        // Read like C's strerror, without Rust's " (os error N)"
+        let msg = match msg.find(" (os error") {
+            Some(end) => msg[..end].to_string(),
+            None => msg,
+        };
+        Error::Io {
+            path: path.display().to_string(),
+            msg,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Directory,
+    Other,
+}
+
+impl Kind {
+    /// The name scripts see, as in `fs.stat(p).type`
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::File => "file",
+            Kind::Directory => "directory",
+            Kind::Other => "other",
+        }
+    }
+}
+
+/// What `fs.stat` reports, following symbolic links
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub kind: Kind,
+    pub size: u64,
+    /// Last modification, in seconds since the epoch like `os.time`
+    pub mtime: i64,
+}
+
+/// How much of the filesystem an `Fs` lets scripts see
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    None,
+    ReadOnly,
+    Full,
+}
+
+/// The filesystem as scripts see it. Sandboxed scripts can be given a
+/// read-only one, where the functions that write fail with `ReadOnly`, or
+/// one with no access, where every function fails with `NoAccess`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fs {
+    access: Access,
+}
+
+impl Default for Fs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fs {
+    pub fn new() -> Self {
+        Self {
+            access: Access::Full,
+        }
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.access = self.access.min(Access::ReadOnly);
+        self
+    }
+
+    pub fn no_access(mut self) -> Self {
+        self.access = Access::None;
+        self
+    }
+
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    fn check_read(&self, func: &'static str) -> Result<()> {
+        match self.access {
+            Access::None => Err(Error::NoAccess(func)),
+            Access::ReadOnly | Access::Full => Ok(()),
+        }
+    }
+
+    fn check_write(&self, func: &'static str) -> Result<()> {
+        self.check_read(func)?;
+        match self.access {
+            Access::Full => Ok(()),
+            _ => Err(Error::ReadOnly(func)),
+        }
+    }
+
+    /// `fs.listdir`: the names in a directory, sorted, without `.` and `..`
+    pub fn listdir(&self, path: impl AsRef<Path>) -> Result<Vec<Vec<u8>>> {
+        self.check_read("listdir")?;
+        let mut names = self.dir(path)?.collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// `fs.dir`: iterate over the names in a directory, in no set order
+    pub fn dir(&self, path: impl AsRef<Path>) -> Result<Dir> {
+        self.check_read("dir")?;
+        let path = path.as_ref();
+        let entries = std::fs::read_dir(path).map_err(io_error(path))?;
+        Ok(Dir {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn stat(&self, path: impl AsRef<Path>) -> Result<Stat> {
+        self.check_read("stat")?;
+        let path = path.as_ref();
+        let metadata = std::fs::metadata(path).map_err(io_error(path))?;
+        let kind = if metadata.is_file() {
+            Kind::File
+        } else if metadata.is_dir() {
+            Kind::Directory
+        } else {
+            Kind::Other
+        };
+        let mtime = metadata.modified().map_err(io_error(path))?;
+        let mtime = match mtime.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        Ok(Stat {
+            kind,
+            size: metadata.len(),
+            mtime,
+        })
+    }
+
+    pub fn exists(&self, path: impl AsRef<Path>) -> Result<bool> {
+        self.check_read("exists")?;
+        Ok(path.as_ref().exists())
+    }
+
+    /// `fs.mkdir`, making one directory in an existing parent
+    pub fn mkdir(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.check_write("mkdir")?;
+        let path = path.as_ref();
+        std::fs::create_dir(path).map_err(io_error(path))
+    }
+
+    /// `fs.rmdir`, removing an empty directory
+    pub fn rmdir(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.check_write("rmdir")?;
+        let path = path.as_ref();
+        std::fs::remove_dir(path).map_err(io_error(path))
+    }
+}
+
+/// The iterator from `Fs::dir`
+pub struct Dir {
+    path: std::path::PathBuf,
+    entries: std::fs::ReadDir,
+}
+
+impl Iterator for Dir {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        Some(
+            entry
+                .map(|entry| name_bytes(entry.file_name()))
+                .map_err(io_error(&self.path)),
+        )
+    }
+}
+
+/// A file name as the bytes of a Lua string
+#[cfg(unix)]
+fn name_bytes(name: OsString) -> Vec<u8> {
+    use std::os::unix::ffi::OsStringExt;
+    name.into_vec()
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: OsString) -> Vec<u8> {
+    name.to_string_lossy().into_owned().into_bytes()
+}
+
+/// The path a Lua string names, so names from `dir` can be passed back
+#[cfg(unix)]
+pub fn path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes.to_vec()))
+}
+
+#[cfg(not(unix))]
+pub fn path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// `fs.join`: the parts joined with `/`, where an absolute part starts
+/// over and empty parts are skipped
+pub fn join(parts: &[&[u8]]) -> Vec<u8> {
+    let mut path = Vec::new();
+    for part in parts.iter().filter(|part| !part.is_empty()) {
+        if part.starts_with(b"/") {
+            path.clear();
+        } else if !path.is_empty() && !path.ends_with(b"/") {
+            path.push(b'/');
+        }
+        path.extend_from_slice(part);
+    }
+    path
+}
+
+/// `fs.normalize`: drop `.` components and repeated `/`, and resolve `..`
+/// against the component before it. This only looks at the bytes, so it
+/// doesn't follow symbolic links.
+pub fn normalize(path: &[u8]) -> Vec<u8> {
+    let absolute = path.starts_with(b"/");
+    let mut parts: Vec<&[u8]> = Vec::new();
+    for part in path.split(|&b| b == b'/') {
+        match part {
+            b"" | b"." => {}
+            b".." => match parts.last() {
+                Some(&last) if last != b".." => {
+                    parts.pop();
+                }
+                // Nothing is above the root
+                _ if absolute => {}
+                _ => parts.push(b".."),
+            },
+            _ => parts.push(part),
+        }
+    }
+    let joined = parts.join(&b'/');
+    match (absolute, joined.is_empty()) {
+        (true, _) => [b"/", &joined[..]].concat(),
+        (false, true) => b".".to_vec(),
+        (false, false) => joined,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        assert_eq!(join(&[b"a", b"b", b"c.lua"]), b"a/b/c.lua");
+        assert_eq!(join(&[b"a/", b"", b"b"]), b"a/b");
+        assert_eq!(join(&[b"a", b"/etc", b"x"]), b"/etc/x");
+        assert_eq!(join(&[]), b"");
+
+        assert_eq!(normalize(b"a/./b/../c"), b"a/c");
+        assert_eq!(normalize(b"a//b/"), b"a/b");
+        assert_eq!(normalize(b"../a/.."), b"..");
+        assert_eq!(normalize(b"a/.."), b".");
+        assert_eq!(normalize(b"/../x"), b"/x");
+        assert_eq!(normalize(b"/"), b"/");
+        assert_eq!(normalize(b""), b".");
+        assert_eq!(normalize(b"caf\xe9/./x/.."), b"caf\xe9");
+    }
+
+    #[test]
+    fn directories() {
+        let fs = Fs::new();
+        let root = std::env::temp_dir().join(format!("lua-fs-test-{}", std::process::id()));
+        fs.mkdir(&root).unwrap();
+        fs.mkdir(root.join("sub")).unwrap();
+        std::fs::write(root.join("b.lua"), "print(1)").unwrap();
+
+        assert_eq!(fs.listdir(&root).unwrap(), [b"b.lua".as_slice(), b"sub"]);
+        assert_eq!(fs.dir(&root).unwrap().count(), 2);
+        let stat = fs.stat(root.join("b.lua")).unwrap();
+        assert_eq!((stat.kind, stat.size), (Kind::File, 8));
+        assert!(stat.mtime > 1_600_000_000);
+        assert_eq!(fs.stat(root.join("sub")).unwrap().kind.name(), "directory");
+        assert_eq!(fs.exists(root.join("sub")), Ok(true));
+
+        let read_only = fs.read_only();
+        assert_eq!(
+            read_only.rmdir(root.join("sub")),
+            Err(Error::ReadOnly("rmdir"))
+        );
+        assert_eq!(
+            Error::ReadOnly("mkdir").to_string(),
+            "'mkdir' not allowed (read-only filesystem)"
+        );
+        assert_eq!(read_only.listdir(&root).unwrap().len(), 2);
+
+        let no_access = read_only.no_access();
+        assert_eq!(no_access.listdir(&root), Err(Error::NoAccess("listdir")));
+        assert!(matches!(no_access.dir(&root), Err(Error::NoAccess("dir"))));
+        assert_eq!(no_access.stat(&root), Err(Error::NoAccess("stat")));
+        assert_eq!(no_access.exists(&root), Err(Error::NoAccess("exists")));
+        assert_eq!(
+            no_access.mkdir(root.join("x")),
+            Err(Error::NoAccess("mkdir"))
+        );
+        assert_eq!(no_access.read_only().access(), Access::None);
+        assert_eq!(
+            Error::NoAccess("stat").to_string(),
+            "'stat' not allowed (no filesystem access)"
+        );
+
+        fs.rmdir(root.join("sub")).unwrap();
+        assert_eq!(fs.exists(root.join("sub")), Ok(false));
+        let missing = root.join("missing");
+        assert_eq!(
+            fs.stat(&missing).unwrap_err().to_string(),
+            format!("{}: No such file or directory", missing.display())
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names() {
+        let fs = Fs::new();
+        let root = std::env::temp_dir().join(format!("lua-fs-names-{}", std::process::id()));
+        fs.mkdir(&root).unwrap();
+        fs.mkdir(root.join(path(b"caf\xe9"))).unwrap();
+
+        let names = fs.listdir(&root).unwrap();
+        assert_eq!(names, [b"caf\xe9"]);
+        let root_name = root.as_os_str().as_encoded_bytes();
+        let dir = path(&join(&[root_name, &names[0]]));
+        assert!(dir.ends_with(path(b"caf\xe9")));
+        assert_eq!(fs.stat(&dir).unwrap().kind, Kind::Directory);
+        fs.rmdir(&dir).unwrap();
+        fs.rmdir(&root).unwrap();
+    }
+}
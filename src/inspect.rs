@@ -0,0 +1,346 @@
+//! The `inspect` built-in: a readable dump of a value, nested tables and
+//! all, for the times `print(t)` showing `table: 0x...` doesn't help.
+//!
+//! Tables live in a slice and refer to each other by index, so they can
+//! share parts and form cycles like real tables do. A table reached more
+//! than once is labelled `<1>{ ... }` the first time and shown as
+//! `<table 1>` after that.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::lex::{Lex, Token};
+use crate::number::Number;
+use crate::value::{self, Scalar};
+
+/// A value to inspect. `Table` is an index into the tables passed along
+/// with it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(Scalar),
+    Table(usize),
+}
+
+const NIL: Value = Value::Scalar(Scalar::Nil);
+
+/// The entries of a table, in table order
+pub type Table = Vec<(Value, Value)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Tables nested deeper than this are shown as `{...}`
+    pub depth: Option<usize>,
+    /// Put each entry on its own line with this many spaces per level,
+    /// instead of all on one line
+    pub indent: Option<usize>,
+    /// Show keys in a fixed order instead of table order
+    pub sort_keys: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            depth: None,
+            indent: Some(2),
+            sort_keys: true,
+        }
+    }
+}
+
+/// `inspect(value, options)`
+pub fn inspect(value: &Value, tables: &[Table], options: &Options) -> Vec<u8> {
+    let mut inspector = Inspector {
+        tables,
+        options,
+        seen: HashMap::new(),
+        labels: HashMap::new(),
+        out: Vec::new(),
+    };
+    inspector.count(value, 0);
+    inspector.value(value, 0);
+    inspector.out
+}
+
+struct Inspector<'a> {
+    tables: &'a [Table],
+    options: &'a Options,
+    /// How many times each table has been reached
+    seen: HashMap<usize, usize>,
+    /// The labels of the tables reached more than once that have been
+    /// shown so far
+    labels: HashMap<usize, usize>,
+    out: Vec<u8>,
+}
+
+impl<'a> Inspector<'a> {
+    fn too_deep(&self, depth: usize) -> bool {
+        self.options.depth.is_some_and(|max| depth > max)
+    }
+
+    /// Count how many times each table is reached, within the depth limit
+    fn count(&mut self, value: &Value, depth: usize) {
+        let Value::Table(table) = *value else {
+            return;
+        };
+        if self.too_deep(depth + 1) {
+            return;
+        }
+        let seen = self.seen.entry(table).or_insert(0);
+        *seen += 1;
+        if *seen > 1 {
+            return;
+        }
+        for (key, value) in self.entries(table) {
+            self.count(key, depth + 1);
+            self.count(value, depth + 1);
+        }
+    }
+
+    /// The entries to show: the array part first, then the other keys
+    fn entries(&self, table: usize) -> Vec<&'a (Value, Value)> {
+        let mut entries: Vec<_> = self.tables[table]
+            .iter()
+            .filter(|(_, value)| *value != NIL)
+            .collect();
+        let len = array_len(&entries);
+        if self.options.sort_keys {
+            entries.sort_by(|a, b| compare_keys(&a.0, &b.0, len));
+        } else {
+            entries.sort_by_key(|(key, _)| array_index(key, len).is_none());
+        }
+        entries
+    }
+
+    fn value(&mut self, value: &Value, depth: usize) {
+        match value {
+            Value::Scalar(Scalar::String(s)) => value::quote(&mut self.out, s),
+            Value::Scalar(scalar) => self.out.extend_from_slice(&scalar.to_display()),
+            Value::Table(table) => self.table(*table, depth + 1),
+        }
+    }
+
+    fn table(&mut self, table: usize, depth: usize) {
+        if self.too_deep(depth) {
+            self.out.extend_from_slice(b"{...}");
+            return;
+        }
+        if self.seen[&table] > 1 {
+            if let Some(label) = self.labels.get(&table) {
+                self.out
+                    .extend_from_slice(format!("<table {}>", label).as_bytes());
+                return;
+            }
+            let label = self.labels.len() + 1;
+            self.labels.insert(table, label);
+            self.out
+                .extend_from_slice(format!("<{}>", label).as_bytes());
+        }
+        let entries = self.entries(table);
+        if entries.is_empty() {
+            self.out.extend_from_slice(b"{}");
+            return;
+        }
+        let len = array_len(&entries);
+        self.out.push(b'{');
+        for (i, (key, value)) in entries.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push(b',');
+            }
+            self.newline(depth);
+            if array_index(key, len).is_none() {
+                self.key(key, depth);
+                self.out.extend_from_slice(b" = ");
+            }
+            self.value(value, depth);
+        }
+        self.newline(depth - 1);
+        self.out.push(b'}');
+    }
+
+    /// A key as a name when it can be, and in brackets otherwise
+    fn key(&mut self, key: &Value, depth: usize) {
+        if let Value::Scalar(Scalar::String(s)) = key
+            && is_name(s)
+        {
+            self.out.extend_from_slice(s);
+            return;
+        }
+        self.out.push(b'[');
+        self.value(key, depth);
+        self.out.push(b']');
+    }
+
+    /// Break the line and indent to `depth`, or leave a space when
+    /// everything goes on one line
+    fn newline(&mut self, depth: usize) {
+        match self.options.indent {
+            Some(indent) => {
+                self.out.push(b'\n');
+                self.out.resize(self.out.len() + indent * depth, b' ');
+            }
+            None => self.out.push(b' '),
+        }
+    }
+}
+
+/// The length of the sequence `1..=n` among the keys of `entries`
+fn array_len(entries: &[&(Value, Value)]) -> i64 {
+    let keys: HashSet<i64> = entries
+        .iter()
+        .filter_map(|(key, _)| match key {
+            Value::Scalar(Scalar::Number(Number::Integer(i))) => Some(*i),
+            _ => None,
+        })
+        .collect();
+    (1..).find(|i| !keys.contains(i)).unwrap() - 1
+}
+
+/// `key`'s place in the array part, if it's in it
+fn array_index(key: &Value, len: i64) -> Option<i64> {
+    match key {
+        Value::Scalar(Scalar::Number(Number::Integer(i))) if (1..=len).contains(i) => Some(*i),
+        _ => None,
+    }
+}
+
+/// Array keys in order, then numbers, booleans, strings and tables
+fn compare_keys(a: &Value, b: &Value, len: i64) -> std::cmp::Ordering {
+    let rank = |key: &Value| match key {
+        _ if array_index(key, len).is_some() => 0,
+        Value::Scalar(Scalar::Number(_)) => 1,
+        Value::Scalar(Scalar::Boolean(_)) => 2,
+        Value::Scalar(Scalar::String(_)) => 3,
+        Value::Table(_) | Value::Scalar(Scalar::Nil) => 4,
+    };
+    let by_value = match (a, b) {
+        (Value::Scalar(Scalar::Number(x)), Value::Scalar(Scalar::Number(y))) => {
+            x.to_float().total_cmp(&y.to_float())
+        }
+        (Value::Scalar(Scalar::Boolean(x)), Value::Scalar(Scalar::Boolean(y))) => x.cmp(y),
+        (Value::Scalar(Scalar::String(x)), Value::Scalar(Scalar::String(y))) => x.cmp(y),
+        (Value::Table(x), Value::Table(y)) => x.cmp(y),
+        _ => std::cmp::Ordering::Equal,
+    };
+    rank(a).cmp(&rank(b)).then(by_value)
+}
+
+/// Whether `s` can be written as a key without brackets
+fn is_name(s: &[u8]) -> bool {
+    let Ok(s) = std::str::from_utf8(s) else {
+        return false;
+    };
+    matches!(Lex::new(s).next(), Ok(Token::Name(name)) if name == s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> Value {
+        Value::Scalar(Scalar::Number(Number::Integer(n)))
+    }
+
+    fn string(s: &str) -> Value {
+        Value::Scalar(Scalar::String(s.as_bytes().to_vec()))
+    }
+
+    fn boolean(b: bool) -> Value {
+        Value::Scalar(Scalar::Boolean(b))
+    }
+
+    fn show(value: &Value, tables: &[Table], options: &Options) -> String {
+        String::from_utf8(inspect(value, tables, options)).unwrap()
+    }
+
+    const ONE_LINE: Options = Options {
+        depth: None,
+        indent: None,
+        sort_keys: true,
+    };
+
+    #[test]
+    fn scalars() {
+        let options = Options::default();
+        assert_eq!(show(&NIL, &[], &options), "nil");
+        assert_eq!(show(&boolean(true), &[], &options), "true");
+        assert_eq!(
+            show(
+                &Value::Scalar(Scalar::Number(Number::Float(2.0))),
+                &[],
+                &options
+            ),
+            "2.0"
+        );
+        assert_eq!(
+            show(&string("a\"b\\\n\x01\x012\r\t"), &[], &options),
+            "\"a\\\"b\\\\\\\n\\1\\0012\\13\\9\""
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let tables = vec![
+            vec![
+                (string("zeta"), boolean(false)),
+                (int(2), string("b")),
+                (string("with space"), int(1)),
+                (int(1), string("a")),
+                (boolean(true), int(2)),
+                (int(10), int(3)),
+                (string("end"), int(4)),
+                (string("sub"), Value::Table(1)),
+                (string("gone"), NIL),
+            ],
+            vec![(string("x"), Value::Table(2))],
+            vec![],
+        ];
+        let root = Value::Table(0);
+        assert_eq!(
+            show(&root, &tables, &ONE_LINE),
+            r#"{ "a", "b", [10] = 3, [true] = 2, ["end"] = 4, sub = { x = {} }, ["with space"] = 1, zeta = false }"#
+        );
+        assert_eq!(
+            show(&Value::Table(1), &tables, &Options::default()),
+            "{\n  x = {}\n}"
+        );
+        let shallow = Options {
+            depth: Some(1),
+            sort_keys: false,
+            ..ONE_LINE
+        };
+        assert_eq!(show(&Value::Table(1), &tables, &shallow), "{ x = {...} }");
+        assert_eq!(
+            show(&root, &tables, &shallow),
+            r#"{ "b", "a", zeta = false, ["with space"] = 1, [true] = 2, [10] = 3, ["end"] = 4, sub = {...} }"#
+        );
+    }
+
+    #[test]
+    fn cycles_and_shared_tables() {
+        // A table holding itself, and a table reached twice
+        let tables = vec![
+            vec![
+                (string("me"), Value::Table(0)),
+                (string("a"), Value::Table(1)),
+                (string("b"), Value::Table(1)),
+            ],
+            vec![(int(1), int(1))],
+        ];
+        assert_eq!(
+            show(&Value::Table(0), &tables, &ONE_LINE),
+            "<1>{ a = <2>{ 1 }, b = <table 2>, me = <table 1> }"
+        );
+        assert_eq!(
+            show(&Value::Table(0), &tables, &Options::default()),
+            "<1>{\n  a = <2>{\n    1\n  },\n  b = <table 2>,\n  me = <table 1>\n}"
+        );
+        // Tables past the depth limit aren't shown, so they aren't shared
+        let shallow = Options {
+            depth: Some(1),
+            ..ONE_LINE
+        };
+        assert_eq!(
+            show(&Value::Table(0), &tables, &shallow),
+            "{ a = {...}, b = {...}, me = {...} }"
+        );
+    }
+}
@@ -0,0 +1,691 @@
+//! The `json` library: `json.encode` and `json.decode` over a tree of
+//! Lua-shaped values. Tables are key-value pairs, the same as in Lua, and
+//! the encoder decides for each one whether it's an array or an object.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use crate::number::{self, Number};
+
+/// A Lua value as seen by the json library. `Null` is the `json.null`
+/// sentinel, which stays distinct from `nil` inside tables.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Null,
+    Boolean(bool),
+    Number(Number),
+    String(Vec<u8>),
+    Table(Vec<(Value, Value)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// An array whose largest index is too large for its element count
+    SparseArray,
+    /// A table key that is neither a string nor a number
+    InvalidKey(&'static str),
+    /// A value with no json representation, like a NaN or a function
+    Unsupported(&'static str),
+    TooDeep,
+    /// Malformed input to `decode`, at a 1-based byte position
+    Syntax {
+        pos: usize,
+        msg: &'static str,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::SparseArray => write!(f, "cannot encode excessively sparse array"),
+            Error::InvalidKey(kind) => write!(f, "cannot encode table key of type {}", kind),
+            Error::Unsupported(what) => write!(f, "cannot encode {}", what),
+            Error::TooDeep => write!(f, "json nesting is too deep"),
+            Error::Syntax { pos, msg } => write!(f, "{} at character {}", msg, pos),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Tables and arrays nested deeper than this are rejected both ways
+const MAX_DEPTH: usize = 1000;
+/// Arrays with holes are never padded with `null` past this length
+const MAX_ARRAY_LEN: usize = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Write object keys in byte order instead of table order
+    pub sort_keys: bool,
+    /// Pretty print with this many spaces per level
+    pub indent: Option<usize>,
+    /// Arrays may have holes as long as the largest index is at most this
+    /// many times the number of elements (or at most 10). Arrays padded
+    /// past 2^24 elements are always too sparse.
+    pub sparse_ratio: usize,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            sort_keys: false,
+            indent: None,
+            sparse_ratio: 2,
+        }
+    }
+}
+
+pub fn encode(value: &Value, options: &EncodeOptions) -> Result<Vec<u8>> {
+    let mut encoder = Encoder {
+        out: Vec::new(),
+        options,
+    };
+    encoder.value(value, 0)?;
+    Ok(encoder.out)
+}
+
+struct Encoder<'o> {
+    out: Vec<u8>,
+    options: &'o EncodeOptions,
+}
+
+impl Encoder<'_> {
+    fn value(&mut self, value: &Value, depth: usize) -> Result<()> {
+        match value {
+            Value::Nil | Value::Null => self.out.extend_from_slice(b"null"),
+            Value::Boolean(b) => self
+                .out
+                .extend_from_slice(if *b { b"true" } else { b"false" }),
+            Value::Number(n) => self.number(*n)?,
+            Value::String(s) => self.string(s)?,
+            Value::Table(entries) => {
+                if depth >= MAX_DEPTH {
+                    return Err(Error::TooDeep);
+                }
+                match self.array_len(entries)? {
+                    Some(len) => self.array(entries, len, depth + 1)?,
+                    None => self.object(entries, depth + 1)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn number(&mut self, n: Number) -> Result<()> {
+        if let Number::Float(f) = n
+            && !f.is_finite()
+        {
+            return Err(Error::Unsupported("inf or nan"));
+        }
+        let text = match n {
+            Number::Integer(i) => i.to_string(),
+            // The shortest text that reads back as the same float
+            Number::Float(f) => format!("{:?}", f),
+        };
+        self.out.extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+
+    fn string(&mut self, s: &[u8]) -> Result<()> {
+        if std::str::from_utf8(s).is_err() {
+            return Err(Error::Unsupported("invalid UTF-8 string"));
+        }
+        self.out.push(b'"');
+        for &b in s {
+            match b {
+                b'"' => self.out.extend_from_slice(b"\\\""),
+                b'\\' => self.out.extend_from_slice(b"\\\\"),
+                b'\n' => self.out.extend_from_slice(b"\\n"),
+                b'\r' => self.out.extend_from_slice(b"\\r"),
+                b'\t' => self.out.extend_from_slice(b"\\t"),
+                0x08 => self.out.extend_from_slice(b"\\b"),
+                0x0c => self.out.extend_from_slice(b"\\f"),
+                0..0x20 | 0x7f => self
+                    .out
+                    .extend_from_slice(format!("\\u{:04x}", b).as_bytes()),
+                _ => self.out.push(b),
+            }
+        }
+        self.out.push(b'"');
+        Ok(())
+    }
+
+    /// The length to encode `entries` as an array with, or `None` when the
+    /// table has keys other than positive integers. Empty tables are objects.
+    fn array_len(&self, entries: &[(Value, Value)]) -> Result<Option<usize>> {
+        let mut max = 0;
+        let mut count: usize = 0;
+        for (key, value) in entries {
+            if *value == Value::Nil {
+                continue;
+            }
+            match array_index(key) {
+                Some(i) => max = max.max(i),
+                None => return Ok(None),
+            }
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(None);
+        }
+        let sparse = max > count.saturating_mul(self.options.sparse_ratio);
+        if max > MAX_ARRAY_LEN || (max > 10 && sparse) {
+            return Err(Error::SparseArray);
+        }
+        Ok(Some(max))
+    }
+
+    fn array(&mut self, entries: &[(Value, Value)], len: usize, depth: usize) -> Result<()> {
+        let mut items = vec![&Value::Nil; len];
+        for (key, value) in entries {
+            if let Some(i) = array_index(key)
+                && *value != Value::Nil
+            {
+                items[i - 1] = value;
+            }
+        }
+        self.out.push(b'[');
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push(b',');
+            }
+            self.newline(depth);
+            self.value(item, depth)?;
+        }
+        self.newline(depth - 1);
+        self.out.push(b']');
+        Ok(())
+    }
+
+    fn object(&mut self, entries: &[(Value, Value)], depth: usize) -> Result<()> {
+        let mut fields = Vec::with_capacity(entries.len());
+        for (key, value) in entries {
+            if *value == Value::Nil {
+                continue;
+            }
+            let key = match key {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string().into_bytes(),
+                Value::Nil => return Err(Error::InvalidKey("nil")),
+                Value::Null => return Err(Error::InvalidKey("userdata")),
+                Value::Boolean(_) => return Err(Error::InvalidKey("boolean")),
+                Value::Table(_) => return Err(Error::InvalidKey("table")),
+            };
+            fields.push((key, value));
+        }
+        if self.options.sort_keys {
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        self.out.push(b'{');
+        let empty = fields.is_empty();
+        for (i, (key, value)) in fields.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push(b',');
+            }
+            self.newline(depth);
+            self.string(&key)?;
+            self.out.push(b':');
+            if self.options.indent.is_some() {
+                self.out.push(b' ');
+            }
+            self.value(value, depth)?;
+        }
+        if !empty {
+            self.newline(depth - 1);
+        }
+        self.out.push(b'}');
+        Ok(())
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if let Some(indent) = self.options.indent {
+            self.out.push(b'\n');
+            self.out.resize(self.out.len() + indent * depth, b' ');
+        }
+    }
+}
+
+/// A key usable as an array index: a positive integer, possibly stored as
+/// an integral float
+fn array_index(key: &Value) -> Option<usize> {
+    let Value::Number(n) = key else {
+        return None;
+    };
+    match n.to_integer() {
+        Ok(i) if i >= 1 => usize::try_from(i).ok(),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DecodeOptions {
+    /// Decode `null` as `Value::Null` instead of leaving the entry out
+    pub null_sentinel: bool,
+}
+
+pub fn decode(input: &[u8], options: &DecodeOptions) -> Result<Value> {
+    if let Err(e) = std::str::from_utf8(input) {
+        return Err(Error::Syntax {
+            pos: e.valid_up_to() + 1,
+            msg: "invalid UTF-8",
+        });
+    }
+    let mut decoder = Decoder {
+        input,
+        pos: 0,
+        options,
+    };
+    decoder.skip_whitespace();
+    let value = decoder.value(0)?;
+    decoder.skip_whitespace();
+    if decoder.pos < input.len() {
+        return Err(decoder.error("expected the end of the input"));
+    }
+    Ok(value)
+}
+
+struct Decoder<'a, 'o> {
+    input: &'a [u8],
+    pos: usize,
+    options: &'o DecodeOptions,
+}
+
+impl Decoder<'_, '_> {
+    fn error(&self, msg: &'static str) -> Error {
+        Error::Syntax {
+            pos: self.pos + 1,
+            msg,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, b: u8, msg: &'static str) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() != Some(b) {
+            return Err(self.error(msg));
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &[u8], value: Value) -> Result<Value> {
+        if !self.input[self.pos..].starts_with(word) {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        match self.peek() {
+            Some(b'{') => self.object(depth + 1),
+            Some(b'[') => self.array(depth + 1),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal(b"true", Value::Boolean(true)),
+            Some(b'f') => self.literal(b"false", Value::Boolean(false)),
+            Some(b'n') => {
+                let null = match self.options.null_sentinel {
+                    true => Value::Null,
+                    false => Value::Nil,
+                };
+                self.literal(b"null", null)
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        self.expect(b'{', "expected '{'")?;
+        let mut entries: Vec<(Value, Value)> = Vec::new();
+        // Where each key is in `entries`
+        let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Table(entries));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.expect(b':', "expected ':'")?;
+            let value = self.value(depth)?;
+            // A repeated key overwrites, like assigning to a table
+            match index.entry(key) {
+                Entry::Occupied(slot) => entries[*slot.get()].1 = value,
+                Entry::Vacant(slot) => {
+                    let key = Value::String(slot.key().clone());
+                    slot.insert(entries.len());
+                    entries.push((key, value));
+                }
+            }
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                }
+                Some(b'}') => {
+                    self.pos += 1;
+                    // Keys set to nil aren't in a table
+                    entries.retain(|(_, value)| *value != Value::Nil);
+                    return Ok(Value::Table(entries));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        self.expect(b'[', "expected '['")?;
+        let mut entries = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Table(entries));
+        }
+        let mut index = 1;
+        loop {
+            let value = self.value(depth)?;
+            if value != Value::Nil {
+                entries.push((Value::Number(Number::Integer(index)), value));
+            }
+            index += 1;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                }
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Table(entries));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        let digits = |d: &mut Self| {
+            let from = d.pos;
+            while let Some(b'0'..=b'9') = d.peek() {
+                d.pos += 1;
+            }
+            d.pos > from
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        if !digits(self) || (self.input[int_start] == b'0' && self.pos - int_start > 1) {
+            return Err(self.error("malformed number"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err(self.error("malformed number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("malformed number"));
+            }
+        }
+        match number::str_to_number(&self.input[start..self.pos]) {
+            Some(n) => Ok(Value::Number(n)),
+            None => Err(self.error("malformed number")),
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        self.pos += 1; // skip opening quote
+        let mut out = Vec::new();
+        loop {
+            let Some(b) = self.peek() else {
+                return Err(self.error("unfinished string"));
+            };
+            self.pos += 1;
+            match b {
+                b'"' => return Ok(out),
+                b'\\' => self.escape(&mut out)?,
+                0..0x20 => {
+                    self.pos -= 1;
+                    return Err(self.error("control character in string"));
+                }
+                _ => out.push(b),
+            }
+        }
+    }
+
+    fn escape(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        let b = self.peek().ok_or_else(|| self.error("unfinished string"))?;
+        self.pos += 1;
+        let c = match b {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\x08',
+            b'f' => '\x0c',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let high = self.hex4()?;
+                let code = if (0xd800..0xdc00).contains(&high) {
+                    if !self.input[self.pos..].starts_with(b"\\u") {
+                        return Err(self.error("missing low surrogate"));
+                    }
+                    self.pos += 2;
+                    let low = self.hex4()?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(self.error("invalid low surrogate"));
+                    }
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    high
+                };
+                char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))?
+            }
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("invalid escape"));
+            }
+        };
+        let mut buf = [0; 4];
+        out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        Ok(())
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(i: i64) -> Value {
+        Value::Number(Number::Integer(i))
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.as_bytes().to_vec())
+    }
+
+    fn encoded(value: &Value, options: &EncodeOptions) -> String {
+        String::from_utf8(encode(value, options).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn encode_values() {
+        let options = EncodeOptions::default();
+        let table = Value::Table(vec![
+            (string("b"), Value::Boolean(true)),
+            (
+                string("a"),
+                Value::Table(vec![(int(1), int(1)), (int(3), int(3))]),
+            ),
+            (string("gone"), Value::Nil),
+            (string("s"), string("q\"\\\n\x01")),
+            (int(7), Value::Number(Number::Float(0.5))),
+        ]);
+        assert_eq!(
+            encoded(&table, &options),
+            r#"{"b":true,"a":[1,null,3],"s":"q\"\\\n\u0001","7":0.5}"#
+        );
+        let sorted = EncodeOptions {
+            sort_keys: true,
+            indent: Some(2),
+            ..options.clone()
+        };
+        assert_eq!(
+            encoded(&table, &sorted),
+            "{\n  \"7\": 0.5,\n  \"a\": [\n    1,\n    null,\n    3\n  ],\n  \"b\": true,\n  \"s\": \"q\\\"\\\\\\n\\u0001\"\n}"
+        );
+        assert_eq!(encoded(&Value::Table(vec![]), &options), "{}");
+        assert_eq!(encoded(&Value::Number(Number::Float(2.0)), &options), "2.0");
+        let float = |f: f64| encoded(&Value::Number(Number::Float(f)), &options);
+        assert_eq!(float(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(float(1e300), "1e300");
+        for f in [0.1 + 0.2, 1e300, -5e-324, 1.0 / 3.0] {
+            assert_eq!(
+                decode(float(f).as_bytes(), &DecodeOptions::default()),
+                Ok(Value::Number(Number::Float(f)))
+            );
+        }
+    }
+
+    #[test]
+    fn encode_errors() {
+        let options = EncodeOptions::default();
+        let sparse = Value::Table(vec![(int(1), int(1)), (int(100), int(2))]);
+        assert_eq!(encode(&sparse, &options), Err(Error::SparseArray));
+        let lenient = EncodeOptions {
+            sparse_ratio: 100,
+            ..options.clone()
+        };
+        assert!(encode(&sparse, &lenient).is_ok());
+        let never_sparse = EncodeOptions {
+            sparse_ratio: usize::MAX,
+            ..options.clone()
+        };
+        assert!(encode(&sparse, &never_sparse).is_ok());
+        let huge = Value::Table(vec![(int(1), int(1)), (int(i64::MAX), int(2))]);
+        assert_eq!(encode(&huge, &never_sparse), Err(Error::SparseArray));
+        let key = Value::Table(vec![(Value::Boolean(true), int(1))]);
+        assert_eq!(encode(&key, &options), Err(Error::InvalidKey("boolean")));
+        assert_eq!(
+            encode(&Value::String(b"\xff".to_vec()), &options),
+            Err(Error::Unsupported("invalid UTF-8 string"))
+        );
+        let nan = Value::Number(Number::Float(f64::NAN));
+        assert_eq!(
+            encode(&nan, &options),
+            Err(Error::Unsupported("inf or nan"))
+        );
+        let mut deep = Value::Nil;
+        for _ in 0..=MAX_DEPTH {
+            deep = Value::Table(vec![(int(1), deep)]);
+        }
+        assert_eq!(encode(&deep, &options), Err(Error::TooDeep));
+    }
+
+    #[test]
+    fn decode_values() {
+        let options = DecodeOptions::default();
+        let value = decode(
+            br#" {"a": [1, -2.5e1, null, "\u00e9\ud83d\ude00"], "a": {}, "n": null, "t": true} "#,
+            &options,
+        );
+        assert_eq!(
+            value,
+            Ok(Value::Table(vec![
+                (string("a"), Value::Table(vec![])),
+                (string("t"), Value::Boolean(true)),
+            ]))
+        );
+        let value = decode(
+            br#"[1, null, 9223372036854775808]"#,
+            &DecodeOptions {
+                null_sentinel: true,
+            },
+        );
+        assert_eq!(
+            value,
+            Ok(Value::Table(vec![
+                (int(1), int(1)),
+                (int(2), Value::Null),
+                (int(3), Value::Number(Number::Float(9223372036854775808.0))),
+            ]))
+        );
+        assert_eq!(
+            decode(br#""\u00e9\ud83d\ude00""#, &options),
+            Ok(string("é😀"))
+        );
+        let keys = (0..50_000).map(|i| format!("\"k{}\":{}", i % 1000, i));
+        let object = format!("{{{}}}", keys.collect::<Vec<_>>().join(","));
+        match decode(object.as_bytes(), &options) {
+            Ok(Value::Table(entries)) => {
+                assert_eq!(entries.len(), 1000);
+                assert_eq!(entries[0], (string("k0"), int(49_000)));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_errors() {
+        let options = DecodeOptions::default();
+        let error = |input: &str| decode(input.as_bytes(), &options).unwrap_err().to_string();
+        assert_eq!(error("[1,]"), "expected a value at character 4");
+        assert_eq!(error("{\"a\" 1}"), "expected ':' at character 6");
+        assert_eq!(error("01"), "malformed number at character 3");
+        assert_eq!(error("\"abc"), "unfinished string at character 5");
+        assert_eq!(error("\"\\x\""), "invalid escape at character 3");
+        assert_eq!(error("\"\\ud800\""), "missing low surrogate at character 8");
+        assert_eq!(
+            error("[1] 2"),
+            "expected the end of the input at character 5"
+        );
+        assert_eq!(error("tru"), "expected a value at character 1");
+        assert_eq!(decode(&b"[".repeat(2000), &options), Err(Error::TooDeep));
+        assert_eq!(
+            decode(b"\"a\xff\"", &options),
+            Err(Error::Syntax {
+                pos: 3,
+                msg: "invalid UTF-8"
+            })
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use std::fs::File;
+use crate::ast::Span;
+use crate::number::{self, Number};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token<'a> {
     // Keywords
     And,
@@ -68,10 +69,289 @@ pub enum Token<'a> {
     Eof,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A character that doesn't start any token
+    UnexpectedSymbol(char),
+    /// A string missing its closing quote before the end of the line
+    UnfinishedString,
+    /// A numeral that doesn't convert to a number
+    MalformedNumber(String),
+    /// A `--[[` comment without its closing bracket
+    UnfinishedLongComment,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedSymbol(c) => write!(f, "unexpected symbol near '{}'", c),
+            Error::UnfinishedString => write!(f, "unfinished string"),
+            Error::MalformedNumber(n) => write!(f, "malformed number near '{}'", n),
+            Error::UnfinishedLongComment => write!(f, "unfinished long comment"),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Input the parser never sees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trivia {
+    Whitespace,
+    Comment,
+}
+
+/// What a piece of source is, for syntax highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenCategory {
+    Keyword,
+    Name,
+    String,
+    Number,
+    Operator,
+    Comment,
+    Whitespace,
+    /// Input the lexer rejected
+    Error,
+}
+
+impl Token<'_> {
+    pub fn category(&self) -> TokenCategory {
+        match self {
+            Token::Name(_) => TokenCategory::Name,
+            Token::String(_) => TokenCategory::String,
+            Token::Integer(_) | Token::Float(_) => TokenCategory::Number,
+            Token::Eof => TokenCategory::Whitespace,
+            Token::And
+            | Token::Break
+            | Token::Do
+            | Token::Else
+            | Token::Elseif
+            | Token::End
+            | Token::False
+            | Token::For
+            | Token::Function
+            | Token::Goto
+            | Token::If
+            | Token::In
+            | Token::Local
+            | Token::Nil
+            | Token::Not
+            | Token::Or
+            | Token::Repeat
+            | Token::Return
+            | Token::Then
+            | Token::True
+            | Token::Until
+            | Token::While => TokenCategory::Keyword,
+            _ => TokenCategory::Operator,
+        }
+    }
+
+    /// The source text of a keyword or operator
+    pub fn text(&self) -> Option<&'static str> {
+        let text = match self {
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Add => "+",
+            Token::Sub => "-",
+            Token::Mul => "*",
+            Token::Div => "/",
+            Token::Mod => "%",
+            Token::Pow => "^",
+            Token::Len => "#",
+            Token::BitAnd => "&",
+            Token::BitXor => "~",
+            Token::BitOr => "|",
+            Token::ShiftL => "<<",
+            Token::ShiftR => ">>",
+            Token::Idiv => "//",
+            Token::Equal => "==",
+            Token::NotEq => "~=",
+            Token::LesEq => "<=",
+            Token::GreEq => ">=",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::Assign => "=",
+            Token::ParL => "(",
+            Token::ParR => ")",
+            Token::CurlyL => "{",
+            Token::CurlyR => "}",
+            Token::SqurL => "[",
+            Token::SqurR => "]",
+            Token::DoubColon => "::",
+            Token::SemiColon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Dots => "...",
+            Token::Name(_)
+            | Token::String(_)
+            | Token::Integer(_)
+            | Token::Float(_)
+            | Token::Eof => return None,
+        };
+        Some(text)
+    }
+
+    /// The token as error messages name it: quoted source text, or
+    /// `<eof>` at the end of the input
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Eof => self.to_string(),
+            _ => format!("'{}'", self),
+        }
+    }
+}
+
+/// Every keyword and operator, for `FromStr`
+const FIXED_TOKENS: [Token<'static>; 55] = [
+    Token::And,
+    Token::Break,
+    Token::Do,
+    Token::Else,
+    Token::Elseif,
+    Token::End,
+    Token::False,
+    Token::For,
+    Token::Function,
+    Token::Goto,
+    Token::If,
+    Token::In,
+    Token::Local,
+    Token::Nil,
+    Token::Not,
+    Token::Or,
+    Token::Repeat,
+    Token::Return,
+    Token::Then,
+    Token::True,
+    Token::Until,
+    Token::While,
+    Token::Add,
+    Token::Sub,
+    Token::Mul,
+    Token::Div,
+    Token::Mod,
+    Token::Pow,
+    Token::Len,
+    Token::BitAnd,
+    Token::BitXor,
+    Token::BitOr,
+    Token::ShiftL,
+    Token::ShiftR,
+    Token::Idiv,
+    Token::Equal,
+    Token::NotEq,
+    Token::LesEq,
+    Token::GreEq,
+    Token::Less,
+    Token::Greater,
+    Token::Assign,
+    Token::ParL,
+    Token::ParR,
+    Token::CurlyL,
+    Token::CurlyR,
+    Token::SqurL,
+    Token::SqurR,
+    Token::DoubColon,
+    Token::SemiColon,
+    Token::Colon,
+    Token::Comma,
+    Token::Dot,
+    Token::Concat,
+    Token::Dots,
+];
+
+/// Writes the token as it appears in source. Strings keep their escapes
+/// and floats are written like `tostring` would.
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "{}", name),
+            Token::String(s) => write!(f, "\"{}\"", s),
+            Token::Integer(i) => write!(f, "{}", i),
+            Token::Float(x) => write!(f, "{}", Number::Float(*x)),
+            Token::Eof => write!(f, "<eof>"),
+            _ => write!(f, "{}", self.text().unwrap()),
+        }
+    }
+}
+
+/// A string that isn't a keyword or operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTokenError;
+
+impl std::fmt::Display for ParseTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "not a keyword or operator")
+    }
+}
+
+/// Parses keywords and operators only, names and literals carry data that
+/// has to come from the lexer
+impl std::str::FromStr for Token<'static> {
+    type Err = ParseTokenError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        FIXED_TOKENS
+            .iter()
+            .find(|token| token.text() == Some(s))
+            .cloned()
+            .ok_or(ParseTokenError)
+    }
+}
+
+/// Classify every byte of `source` for syntax highlighting. The spans are
+/// byte offsets that cover the whole input in order, including whitespace
+/// and comments. Input the lexer rejects comes out as
+/// `TokenCategory::Error` and lexing carries on after it.
+pub fn highlight(source: &str) -> Vec<(Span, TokenCategory)> {
+    let mut lex = Lex::new(source);
+    let mut spans = Vec::new();
+    loop {
+        let start = lex.pos;
+        let category = match lex.trivia() {
+            Ok(Some(Trivia::Whitespace)) => TokenCategory::Whitespace,
+            Ok(Some(Trivia::Comment)) => TokenCategory::Comment,
+            Ok(None) => match lex.token() {
+                Ok(Token::Eof) => break,
+                Ok(token) => token.category(),
+                Err(_) => TokenCategory::Error,
+            },
+            Err(_) => TokenCategory::Error,
+        };
+        spans.push((Span::new(start as u32, lex.pos as u32), category));
+    }
+    spans
+}
+
 #[derive(Debug)]
 pub struct Lex<'a> {
     input: &'a str,
     pos: usize,
+    token_start: usize,
     line_number: u32,
     line_pos_offset: usize,
 }
@@ -81,23 +361,43 @@ impl<'a> Lex<'a> {
         Self {
             input,
             pos: 0,
+            token_start: 0,
             line_number: 1,
             line_pos_offset: 0,
         }
     }
 
-    pub fn next(&mut self) -> Token<'a> {
-        while let Some(b) = self.peek_byte() {
-            match b {
-                b' ' | b'\t' => self.pos += 1,
-                b'\r' | b'\n' => self.next_line(),
-                b'a'..=b'z' | b'A'..=b'Z' | b'_' => return self.lex_identifier(),
-                b'0'..=b'9' => return self.lex_number(),
-                b'"' => return Token::String(self.lex_string()),
-                _ => return self.lex_operator(),
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Token<'a>> {
+        while self.trivia()?.is_some() {}
+        self.token_start = self.pos;
+        self.token()
+    }
+
+    /// Continue lexing from byte offset `pos`, which must be at a token or
+    /// trivia boundary
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = 0;
+        self.line_number = 1;
+        self.line_pos_offset = 0;
+        while self.pos < pos {
+            match self.peek_byte() {
+                Some(b'\r' | b'\n') => self.next_line(),
+                _ => self.pos += 1,
             }
         }
-        Token::Eof
+        self.pos = pos;
+        self.token_start = pos;
+    }
+
+    /// The whole input being lexed
+    pub fn source(&self) -> &'a str {
+        self.input
+    }
+
+    /// Byte offsets of the token last returned by `next`
+    pub fn token_span(&self) -> Span {
+        Span::new(self.token_start as u32, self.pos as u32)
     }
 
     pub fn line_number(&self) -> u32 {
@@ -108,18 +408,97 @@ impl<'a> Lex<'a> {
         self.pos - self.line_pos_offset + 1
     }
 
+    /// Consume a run of whitespace or a single comment
+    fn trivia(&mut self) -> Result<Option<Trivia>> {
+        match self.peek_byte() {
+            Some(b' ' | b'\t' | b'\x0b' | b'\x0c' | b'\r' | b'\n') => {
+                while let Some(b) = self.peek_byte() {
+                    match b {
+                        b' ' | b'\t' | b'\x0b' | b'\x0c' => self.pos += 1,
+                        b'\r' | b'\n' => self.next_line(),
+                        _ => break,
+                    }
+                }
+                Ok(Some(Trivia::Whitespace))
+            }
+            Some(b'-') if self.peek_byte_at(1) == Some(b'-') => {
+                self.lex_comment()?;
+                Ok(Some(Trivia::Comment))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Lex the token at the current position, which must not be trivia
+    fn token(&mut self) -> Result<Token<'a>> {
+        match self.peek_byte() {
+            None => Ok(Token::Eof),
+            Some(b'a'..=b'z' | b'A'..=b'Z' | b'_') => Ok(self.lex_identifier()),
+            Some(b'0'..=b'9') => self.lex_number(),
+            Some(b'"') => self.lex_string().map(Token::String),
+            Some(_) => self.lex_operator(),
+        }
+    }
+
     fn next_line(&mut self) {
+        let b = self.peek_byte();
         self.pos += 1;
+        // \r\n and \n\r count as a single line break
+        let other = if b == Some(b'\n') { b'\r' } else { b'\n' };
+        if self.peek_byte() == Some(other) {
+            self.pos += 1;
+        }
         self.line_number += 1;
         self.line_pos_offset = self.pos;
     }
 
     fn peek_byte(&self) -> Option<u8> {
-        if self.pos < self.input.len() {
-            Some(self.input.as_bytes()[self.pos])
-        } else {
-            None
+        self.peek_byte_at(0)
+    }
+
+    fn peek_byte_at(&self, offset: usize) -> Option<u8> {
+        self.input.as_bytes().get(self.pos + offset).copied()
+    }
+
+    /// Skip a `--` comment, either to the end of the line or, for
+    /// `--[[ ... ]]` and `--[==[ ... ]==]`, to the matching bracket
+    fn lex_comment(&mut self) -> Result<()> {
+        self.pos += 2; // skip --
+        if let Some(level) = self.long_bracket_level() {
+            self.pos += level + 2;
+            let close = format!("]{}]", "=".repeat(level));
+            loop {
+                match self.peek_byte() {
+                    None => return Err(Error::UnfinishedLongComment),
+                    Some(b'\r' | b'\n') => self.next_line(),
+                    Some(b']') if self.input[self.pos..].starts_with(&close) => {
+                        self.pos += close.len();
+                        return Ok(());
+                    }
+                    Some(_) => self.pos += 1,
+                }
+            }
         }
+        while let Some(b) = self.peek_byte() {
+            if b == b'\r' || b == b'\n' {
+                break;
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// The number of `=` in an opening long bracket (`[[`, `[=[`, ...) at the
+    /// current position, if there is one
+    fn long_bracket_level(&self) -> Option<usize> {
+        if self.peek_byte() != Some(b'[') {
+            return None;
+        }
+        let mut level = 0;
+        while self.peek_byte_at(level + 1) == Some(b'=') {
+            level += 1;
+        }
+        (self.peek_byte_at(level + 1) == Some(b'[')).then_some(level)
     }
 
     fn lex_identifier(&mut self) -> Token<'a> {
@@ -133,7 +512,7 @@ impl<'a> Lex<'a> {
         }
         let ident = &self.input[start..self.pos];
 
-        return match ident {
+        match ident {
             "and" => Token::And,
             "break" => Token::Break,
             "do" => Token::Do,
@@ -154,20 +533,21 @@ impl<'a> Lex<'a> {
             "return" => Token::Return,
             "then" => Token::Then,
             "true" => Token::True,
-            "Until" => Token::Until,
+            "until" => Token::Until,
             "while" => Token::While,
             _ => Token::Name(ident),
-        };
+        }
     }
 
-    fn lex_operator(&mut self) -> Token<'a> {
+    fn lex_operator(&mut self) -> Result<Token<'a>> {
         let b = self.peek_byte().unwrap();
         self.pos += 1;
 
-        match b {
+        let token = match b {
             b'+' => Token::Add,
             b'-' => Token::Sub,
             b'*' => Token::Mul,
+            b'%' => Token::Mod,
             b'/' => {
                 if self.peek_byte() == Some(b'/') {
                     self.pos += 1;
@@ -205,25 +585,31 @@ impl<'a> Lex<'a> {
                     Token::Dot
                 }
             }
-            b'<' => {
-                if self.peek_byte() == Some(b'=') {
+            b'<' => match self.peek_byte() {
+                Some(b'=') => {
                     self.pos += 1;
                     Token::LesEq
-                } else {
-                    Token::Less
                 }
-            }
-            b'>' => {
-                if self.peek_byte() == Some(b'=') {
+                Some(b'<') => {
+                    self.pos += 1;
+                    Token::ShiftL
+                }
+                _ => Token::Less,
+            },
+            b'>' => match self.peek_byte() {
+                Some(b'=') => {
                     self.pos += 1;
                     Token::GreEq
-                } else {
-                    Token::Greater
                 }
-            }
+                Some(b'>') => {
+                    self.pos += 1;
+                    Token::ShiftR
+                }
+                _ => Token::Greater,
+            },
             b'&' => Token::BitAnd,
             b'|' => Token::BitOr,
-            b'^' => Token::BitXor,
+            b'^' => Token::Pow,
             b'#' => Token::Len,
             b'(' => Token::ParL,
             b')' => Token::ParR,
@@ -241,53 +627,133 @@ impl<'a> Lex<'a> {
             }
             b';' => Token::SemiColon,
             b',' => Token::Comma,
-            _ => panic!("Unknown operator: {}", b as char),
-        }
+            _ => {
+                // Step over the whole character, it may be multi-byte
+                let c = self.input[self.pos - 1..].chars().next().unwrap();
+                self.pos += c.len_utf8() - 1;
+                return Err(Error::UnexpectedSymbol(c));
+            }
+        };
+        Ok(token)
     }
 
-    fn lex_number(&mut self) -> Token<'a> {
+    /// Reads a numeral the way the reference `read_numeral` does: greedily
+    /// take anything that could be part of one and let the conversion
+    /// decide whether it is well formed
+    fn lex_number(&mut self) -> Result<Token<'a>> {
         let start = self.pos;
-        let mut has_dot = false;
-        let mut has_exp = false;
+        let mut exponent = [b'e', b'E'];
+        if self.input[self.pos..].starts_with("0x") || self.input[self.pos..].starts_with("0X") {
+            self.pos += 2;
+            exponent = [b'p', b'P'];
+        }
 
         while let Some(b) = self.peek_byte() {
-            match b {
-                b'0'..=b'9' => self.pos += 1,
-                b'.' if !has_dot && !has_exp => {
-                    has_dot = true;
-                    self.pos += 1;
-                }
-                b'e' | b'E' if !has_exp => {
-                    has_exp = true;
+            if exponent.contains(&b) {
+                self.pos += 1;
+                if let Some(b'+' | b'-') = self.peek_byte() {
                     self.pos += 1;
-                    if let Some(b'+' | b'-') = self.peek_byte() {
-                        self.pos += 1;
-                    }
                 }
-                _ => break,
+            } else if b.is_ascii_hexdigit() || b == b'.' {
+                self.pos += 1;
+            } else {
+                break;
             }
         }
+        // A letter touching the numeral makes it malformed, as in `3x`
+        if let Some(b'a'..=b'z' | b'A'..=b'Z' | b'_') = self.peek_byte() {
+            self.pos += 1;
+        }
 
         let slice = &self.input[start..self.pos];
 
-        match has_dot || has_exp {
-            true => Token::Float(slice.parse().unwrap()),
-            false => Token::Integer(slice.parse().unwrap()),
+        match number::str_to_number(slice.as_bytes()) {
+            Some(Number::Integer(i)) => Ok(Token::Integer(i)),
+            Some(Number::Float(f)) => Ok(Token::Float(f)),
+            None => Err(Error::MalformedNumber(slice.to_string())),
         }
     }
 
-    fn lex_string(&mut self) -> &'a str {
+    fn lex_string(&mut self) -> Result<&'a str> {
         self.pos += 1; // skip opening quote
         let start = self.pos;
-        while let Some(b) = self.peek_byte() {
-            if b == b'"' {
-                break;
+        loop {
+            match self.peek_byte() {
+                Some(b'"') => break,
+                Some(b'\r' | b'\n') | None => return Err(Error::UnfinishedString),
+                Some(_) => self.pos += 1,
             }
-            self.pos += 1;
         }
         let s = &self.input[start..self.pos];
         self.pos += 1; // skip closing quote
-        s
+        Ok(s)
+    }
+}
+
+/// A token recorded in a `TokenStream`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexeme<'a> {
+    pub token: Token<'a>,
+    /// Whitespace and comments just before the token
+    pub leading: &'a str,
+    pub span: Span,
+    /// Where the lexer was after the token, for error messages
+    pub line: u32,
+    pub column: usize,
+}
+
+/// The tokens of a whole chunk with their trivia, to be inspected or
+/// rewritten before `Parser::from_tokens` parses them. Tokens added by a
+/// rewrite can take the span and position of a neighbour so node spans
+/// and errors still point somewhere sensible.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenStream<'a> {
+    pub lexemes: Vec<Lexeme<'a>>,
+    /// Whitespace and comments after the last token
+    pub trailing: &'a str,
+}
+
+impl<'a> TokenStream<'a> {
+    /// Lex everything `lexer` has left. On an error the lexer stays where
+    /// it happened, so its position can go into the message.
+    pub fn record(lexer: &mut Lex<'a>) -> Result<Self> {
+        let mut lexemes = Vec::new();
+        loop {
+            let leading_start = lexer.pos;
+            let token = lexer.next()?;
+            let leading = &lexer.input[leading_start..lexer.token_start];
+            if token == Token::Eof {
+                return Ok(Self {
+                    lexemes,
+                    trailing: leading,
+                });
+            }
+            lexemes.push(Lexeme {
+                token,
+                leading,
+                span: lexer.token_span(),
+                line: lexer.line_number(),
+                column: lexer.line_position(),
+            });
+        }
+    }
+}
+
+impl<'a> FromIterator<Lexeme<'a>> for TokenStream<'a> {
+    fn from_iter<I: IntoIterator<Item = Lexeme<'a>>>(iter: I) -> Self {
+        Self {
+            lexemes: iter.into_iter().collect(),
+            trailing: "",
+        }
+    }
+}
+
+impl<'a> IntoIterator for TokenStream<'a> {
+    type Item = Lexeme<'a>;
+    type IntoIter = std::vec::IntoIter<Lexeme<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lexemes.into_iter()
     }
 }
 
@@ -298,24 +764,183 @@ mod tests {
     #[test]
     fn lex_numbers() {
         let mut lex = Lex::new("123 4.56 444 4.55555555 4.57e-3 0.3e12 5e+20");
-        assert_eq!(lex.next(), Token::Integer(123));
-        assert_eq!(lex.next(), Token::Float(4.56));
-        assert_eq!(lex.next(), Token::Integer(444));
-        assert_eq!(lex.next(), Token::Float(4.55555555));
-        assert_eq!(lex.next(), Token::Float(4.57e-3));
-        assert_eq!(lex.next(), Token::Float(0.3e12));
-        assert_eq!(lex.next(), Token::Float(5e+20));
-        assert_eq!(lex.next(), Token::Eof);
+        assert_eq!(lex.next(), Ok(Token::Integer(123)));
+        assert_eq!(lex.next(), Ok(Token::Float(4.56)));
+        assert_eq!(lex.next(), Ok(Token::Integer(444)));
+        assert_eq!(lex.next(), Ok(Token::Float(4.55555555)));
+        assert_eq!(lex.next(), Ok(Token::Float(4.57e-3)));
+        assert_eq!(lex.next(), Ok(Token::Float(0.3e12)));
+        assert_eq!(lex.next(), Ok(Token::Float(5e+20)));
+        assert_eq!(lex.next(), Ok(Token::Eof));
+
+        let mut lex = Lex::new("0xff 0XA.8p1 0xffffffffffffffff 0x10000000000000001");
+        assert_eq!(lex.next(), Ok(Token::Integer(255)));
+        assert_eq!(lex.next(), Ok(Token::Float(21.0)));
+        assert_eq!(lex.next(), Ok(Token::Integer(-1)));
+        assert_eq!(lex.next(), Ok(Token::Integer(1)));
+
+        let mut lex = Lex::new("9223372036854775807 9223372036854775808");
+        assert_eq!(lex.next(), Ok(Token::Integer(i64::MAX)));
+        assert_eq!(lex.next(), Ok(Token::Float(9223372036854775808.0)));
+    }
+
+    #[test]
+    fn malformed_numbers() {
+        for source in ["1e", "0x", "3x", "1..2", "0x1p", "12ab"] {
+            assert_eq!(
+                Lex::new(source).next().unwrap_err().to_string(),
+                format!("malformed number near '{}'", source)
+            );
+        }
+        let mut lex = Lex::new("1 ..2");
+        assert_eq!(lex.next(), Ok(Token::Integer(1)));
+        assert_eq!(lex.next(), Ok(Token::Concat));
+    }
+
+    #[test]
+    fn token_text_round_trips() {
+        for token in &FIXED_TOKENS {
+            let text = token.to_string();
+            assert_eq!(text.parse::<Token>().as_ref(), Ok(token));
+            let mut lex = Lex::new(&text);
+            assert_eq!(lex.next().as_ref(), Ok(token), "{:?}", text);
+        }
+        assert_eq!("foo".parse::<Token>(), Err(ParseTokenError));
+        assert_eq!(Token::Float(1.0).to_string(), "1.0");
+        assert_eq!(Token::String("a\\n").to_string(), "\"a\\n\"");
+        assert_eq!(Token::ParR.describe(), "')'");
+        assert_eq!(Token::Name("x").describe(), "'x'");
+        assert_eq!(Token::Eof.describe(), "<eof>");
+    }
+
+    #[test]
+    fn record_token_stream() {
+        let source = "-- hi\nf(x) --[[ bye ]]\n";
+        let stream = TokenStream::record(&mut Lex::new(source)).unwrap();
+        let tokens: Vec<_> = stream.lexemes.iter().map(|l| l.token.clone()).collect();
+        assert_eq!(
+            tokens,
+            [Token::Name("f"), Token::ParL, Token::Name("x"), Token::ParR]
+        );
+        assert_eq!(stream.lexemes[0].leading, "-- hi\n");
+        assert_eq!(stream.lexemes[0].span, Span::new(6, 7));
+        assert_eq!((stream.lexemes[0].line, stream.lexemes[0].column), (2, 2));
+        assert_eq!(stream.lexemes[1].leading, "");
+        assert_eq!(stream.trailing, " --[[ bye ]]\n");
+
+        let mut lex = Lex::new("f(\n@)");
+        assert_eq!(
+            TokenStream::record(&mut lex),
+            Err(Error::UnexpectedSymbol('@'))
+        );
+        assert_eq!(lex.line_number(), 2);
     }
 
     #[test]
     fn lex_identifiers_and_keywords() {
         let mut lex = Lex::new("if x then end foo_bar");
-        assert_eq!(lex.next(), Token::If);
-        assert_eq!(lex.next(), Token::Name("x"));
-        assert_eq!(lex.next(), Token::Then);
-        assert_eq!(lex.next(), Token::End);
-        assert_eq!(lex.next(), Token::Name("foo_bar"));
-        assert_eq!(lex.next(), Token::Eof);
+        assert_eq!(lex.next(), Ok(Token::If));
+        assert_eq!(lex.next(), Ok(Token::Name("x")));
+        assert_eq!(lex.next(), Ok(Token::Then));
+        assert_eq!(lex.next(), Ok(Token::End));
+        assert_eq!(lex.next(), Ok(Token::Name("foo_bar")));
+        assert_eq!(lex.next(), Ok(Token::Eof));
+    }
+
+    #[test]
+    fn lex_bitwise_operators() {
+        let mut lex = Lex::new("a & b | c ~ d << 1 >> 2 ~= ^ <= >=");
+        assert_eq!(lex.next(), Ok(Token::Name("a")));
+        assert_eq!(lex.next(), Ok(Token::BitAnd));
+        assert_eq!(lex.next(), Ok(Token::Name("b")));
+        assert_eq!(lex.next(), Ok(Token::BitOr));
+        assert_eq!(lex.next(), Ok(Token::Name("c")));
+        assert_eq!(lex.next(), Ok(Token::BitXor));
+        assert_eq!(lex.next(), Ok(Token::Name("d")));
+        assert_eq!(lex.next(), Ok(Token::ShiftL));
+        assert_eq!(lex.next(), Ok(Token::Integer(1)));
+        assert_eq!(lex.next(), Ok(Token::ShiftR));
+        assert_eq!(lex.next(), Ok(Token::Integer(2)));
+        assert_eq!(lex.next(), Ok(Token::NotEq));
+        assert_eq!(lex.next(), Ok(Token::Pow));
+        assert_eq!(lex.next(), Ok(Token::LesEq));
+        assert_eq!(lex.next(), Ok(Token::GreEq));
+        assert_eq!(lex.next(), Ok(Token::Eof));
+    }
+
+    #[test]
+    fn lex_errors() {
+        let mut lex = Lex::new("x @ \"abc\n");
+        assert_eq!(lex.next(), Ok(Token::Name("x")));
+        assert_eq!(lex.next(), Err(Error::UnexpectedSymbol('@')));
+        assert_eq!(lex.next(), Err(Error::UnfinishedString));
+
+        let mut lex = Lex::new("1e+ é");
+        assert_eq!(
+            lex.next().unwrap_err().to_string(),
+            "malformed number near '1e+'"
+        );
+        assert_eq!(lex.next(), Err(Error::UnexpectedSymbol('é')));
+        assert_eq!(lex.next(), Ok(Token::Eof));
+    }
+
+    #[test]
+    fn lex_line_breaks() {
+        let mut lex = Lex::new("a\r\nb\n\rc\n\nd");
+        assert_eq!(lex.next(), Ok(Token::Name("a")));
+        assert_eq!(lex.next(), Ok(Token::Name("b")));
+        assert_eq!(lex.line_number(), 2);
+        assert_eq!(lex.next(), Ok(Token::Name("c")));
+        assert_eq!(lex.line_number(), 3);
+        assert_eq!(lex.next(), Ok(Token::Name("d")));
+        assert_eq!(lex.line_number(), 5);
+    }
+
+    #[test]
+    fn lex_comments() {
+        let mut lex = Lex::new("a -- line\nb --[[ block\n ]] c --[==[ ]] ]==] d - -e");
+        assert_eq!(lex.next(), Ok(Token::Name("a")));
+        assert_eq!(lex.next(), Ok(Token::Name("b")));
+        assert_eq!(lex.next(), Ok(Token::Name("c")));
+        assert_eq!(lex.line_number(), 3);
+        assert_eq!(lex.next(), Ok(Token::Name("d")));
+        assert_eq!(lex.next(), Ok(Token::Sub));
+        assert_eq!(lex.next(), Ok(Token::Sub));
+        assert_eq!(lex.next(), Ok(Token::Name("e")));
+        assert_eq!(lex.next(), Ok(Token::Eof));
+
+        let mut lex = Lex::new("--[[ never closed");
+        assert_eq!(lex.next(), Err(Error::UnfinishedLongComment));
+    }
+
+    #[test]
+    fn highlight_covers_every_byte() {
+        let source = "local x = 1.5 -- note\nprint(\"hi\") @";
+        let spans = highlight(source);
+        let text: Vec<_> = spans
+            .iter()
+            .map(|(span, category)| (&source[span.start as usize..span.end as usize], *category))
+            .collect();
+        assert_eq!(
+            text,
+            [
+                ("local", TokenCategory::Keyword),
+                (" ", TokenCategory::Whitespace),
+                ("x", TokenCategory::Name),
+                (" ", TokenCategory::Whitespace),
+                ("=", TokenCategory::Operator),
+                (" ", TokenCategory::Whitespace),
+                ("1.5", TokenCategory::Number),
+                (" ", TokenCategory::Whitespace),
+                ("-- note", TokenCategory::Comment),
+                ("\n", TokenCategory::Whitespace),
+                ("print", TokenCategory::Name),
+                ("(", TokenCategory::Operator),
+                ("\"hi\"", TokenCategory::String),
+                (")", TokenCategory::Operator),
+                (" ", TokenCategory::Whitespace),
+                ("@", TokenCategory::Error),
+            ]
+        );
     }
 }
@@ -0,0 +1,19 @@
+pub mod ast;
+pub mod bit;
+pub mod buffer;
+pub mod date;
+pub mod fs;
+// pub mod instruction;
+pub mod inspect;
+pub mod json;
+pub mod lex;
+pub mod number;
+pub mod pack;
+pub mod parse;
+pub mod pattern;
+pub mod random;
+pub mod sort;
+pub mod time;
+pub mod value;
+// pub mod vm;
+pub mod warn;
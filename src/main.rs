@@ -1,41 +1,74 @@
-pub mod ast;
-pub mod instruction;
-pub mod lex;
-pub mod parse;
-// pub mod vm;
-
-use std::{env, fmt, fs::File, io::Read};
-
-use crate::{
-    lex::{Lex, Token},
-    parse::Parser,
+use std::{
+    env,
+    fs::File,
+    io::{IsTerminal, Read},
+    process::ExitCode,
 };
 
-// use crate::vm::VM;
+use lua::{lex::Lex, parse::Parser};
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().collect();
+    let ast = args.get(1).is_some_and(|arg| arg == "--ast");
+    if ast {
+        args.remove(1);
+    }
+    // `-`, or no script with input piped in, reads the chunk from stdin
+    let script = match &args[..] {
+        [_] if !std::io::stdin().is_terminal() => None,
+        [_, script] if script == "-" => None,
+        [_, script] => Some(script.as_str()),
+        _ => {
+            eprintln!("usage: {} [--ast] [script | -]", args[0]);
+            return ExitCode::FAILURE;
+        }
+    };
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} scripts", args[0]);
-        return;
+    // Errors are reported like the reference interpreter, so scripts and CI
+    // can rely on the message and the exit status
+    match run(script, ast) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(msg) => {
+            eprintln!("lua: {}", msg);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    let mut file = File::open(&args[1]).unwrap();
+/// Parse `script`, or stdin when it is `None`, and print the syntax tree
+/// to stdout if `ast` is set
+fn run(script: Option<&str>, ast: bool) -> Result<(), String> {
     let mut source = String::new();
-    file.read_to_string(&mut source).unwrap();
+    let chunk_name = match script {
+        Some(script) => {
+            File::open(script)
+                .and_then(|mut file| file.read_to_string(&mut source))
+                .map_err(|e| format!("cannot open {}: {}", script, io_message(&e)))?;
+            format!("@{}", script)
+        }
+        None => {
+            std::io::stdin()
+                .read_to_string(&mut source)
+                .map_err(|e| format!("cannot read stdin: {}", io_message(&e)))?;
+            "=stdin".to_string()
+        }
+    };
 
-    // Create a lexer
     let lex = Lex::new(&source);
-    let mut parser = Parser::new(lex);
-    // Lex and print all tokens
-    // loop {
-    //     let token = lex.next();
-    //     if token == Token::Eof {
-    //         break;
-    //     }
-    // }
+    let mut parser = Parser::with_chunk_name(lex, &chunk_name).map_err(|e| e.to_string())?;
+    let chunk = parser.parse().map_err(|e| e.to_string())?;
+    if ast {
+        println!("{:#?}", chunk);
+    }
+    Ok(())
+}
 
-    dbg!(parser.parse().unwrap());
-    // dbg!(parser.constants);
-    // dbg!(parser.code);
+/// The message of an I/O error without Rust's " (os error N)" suffix, to
+/// read like C's strerror
+fn io_message(e: &std::io::Error) -> String {
+    let msg = e.to_string();
+    match msg.find(" (os error") {
+        Some(end) => msg[..end].to_string(),
+        None => msg,
+    }
 }
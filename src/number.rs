@@ -0,0 +1,511 @@
+/// A Lua number, either a 64-bit integer or a double
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Integer `//` or `%` with a zero divisor
+    DivByZero(&'static str),
+    /// Bitwise operand that is a float without an exact integer value
+    NoIntegerRep,
+    /// `tonumber` with a base outside 2 to 36
+    BaseOutOfRange,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::DivByZero(op) => write!(f, "attempt to perform 'n{}0'", op),
+            Error::NoIntegerRep => write!(f, "number has no integer representation"),
+            Error::BaseOutOfRange => write!(f, "bad argument #2 to 'tonumber' (base out of range)"),
+        }
+    }
+}
+
+/// Formats like `tostring`: integers in decimal, floats with `%.14g` and a
+/// `.0` suffix when they would otherwise read back as integers
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Integer(i) => write!(f, "{}", i),
+            Number::Float(x) => {
+                let s = format_g(*x, 14);
+                if s.bytes().all(|b| b == b'-' || b.is_ascii_digit()) {
+                    write!(f, "{}.0", s)
+                } else {
+                    write!(f, "{}", s)
+                }
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Number {
+    pub fn to_float(self) -> f64 {
+        match self {
+            Number::Integer(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    /// Convert to an integer for bitwise operations. Floats only convert
+    /// when they hold an integral value that fits in an i64.
+    pub fn to_integer(self) -> Result<i64> {
+        match self {
+            Number::Integer(i) => Ok(i),
+            Number::Float(f) => float_to_integer(f).ok_or(Error::NoIntegerRep),
+        }
+    }
+
+    pub fn band(self, rhs: Number) -> Result<Number> {
+        Ok(Number::Integer(self.to_integer()? & rhs.to_integer()?))
+    }
+
+    pub fn bor(self, rhs: Number) -> Result<Number> {
+        Ok(Number::Integer(self.to_integer()? | rhs.to_integer()?))
+    }
+
+    pub fn bxor(self, rhs: Number) -> Result<Number> {
+        Ok(Number::Integer(self.to_integer()? ^ rhs.to_integer()?))
+    }
+
+    /// Unary `~`
+    pub fn bnot(self) -> Result<Number> {
+        Ok(Number::Integer(!self.to_integer()?))
+    }
+
+    pub fn shiftl(self, rhs: Number) -> Result<Number> {
+        Ok(Number::Integer(shift_left(
+            self.to_integer()?,
+            rhs.to_integer()?,
+        )))
+    }
+
+    pub fn shiftr(self, rhs: Number) -> Result<Number> {
+        Ok(Number::Integer(shift_left(
+            self.to_integer()?,
+            rhs.to_integer()?.wrapping_neg(),
+        )))
+    }
+
+    /// Floor division (`//`). Integer operands stay integers, anything else
+    /// is done in floats. Integer division by zero is an `Err`; float
+    /// division by zero gives an infinity or NaN.
+    pub fn idiv(self, rhs: Number) -> Result<Number> {
+        match (self, rhs) {
+            (Number::Integer(a), Number::Integer(b)) => int_idiv(a, b).map(Number::Integer),
+            (a, b) => Ok(Number::Float(float_idiv(a.to_float(), b.to_float()))),
+        }
+    }
+
+    /// Modulo (`%`), with the result taking the sign of the divisor.
+    pub fn modulo(self, rhs: Number) -> Result<Number> {
+        match (self, rhs) {
+            (Number::Integer(a), Number::Integer(b)) => int_mod(a, b).map(Number::Integer),
+            (a, b) => Ok(Number::Float(float_mod(a.to_float(), b.to_float()))),
+        }
+    }
+}
+
+/// Exact float to integer conversion, `None` for fractions, NaN, inf and
+/// values outside the i64 range
+pub fn float_to_integer(f: f64) -> Option<i64> {
+    // 2^63 is exactly representable, i64::MAX is not
+    if f.floor() == f && (-9223372036854775808.0..9223372036854775808.0).contains(&f) {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
+
+/// Logical shift, negative counts shift right and counts of 64 or more
+/// shift everything out
+pub fn shift_left(x: i64, n: i64) -> i64 {
+    if n <= -64 || n >= 64 {
+        0
+    } else if n < 0 {
+        ((x as u64) >> -n) as i64
+    } else {
+        ((x as u64) << n) as i64
+    }
+}
+
+/// Integer floor division, rounding towards negative infinity
+pub fn int_idiv(a: i64, b: i64) -> Result<i64> {
+    match b {
+        0 => Err(Error::DivByZero("//")),
+        // i64::MIN // -1 overflows, Lua wraps around
+        -1 => Ok(a.wrapping_neg()),
+        _ => {
+            let q = a / b;
+            if a % b != 0 && (a ^ b) < 0 {
+                Ok(q - 1)
+            } else {
+                Ok(q)
+            }
+        }
+    }
+}
+
+/// Integer modulo, `a - (a // b) * b`
+pub fn int_mod(a: i64, b: i64) -> Result<i64> {
+    match b {
+        0 => Err(Error::DivByZero("%")),
+        -1 => Ok(0),
+        _ => {
+            let r = a % b;
+            if r != 0 && (r ^ b) < 0 {
+                Ok(r + b)
+            } else {
+                Ok(r)
+            }
+        }
+    }
+}
+
+/// Float floor division, division by zero gives ±inf or NaN
+pub fn float_idiv(a: f64, b: f64) -> f64 {
+    (a / b).floor()
+}
+
+/// Float modulo, division by zero gives NaN
+pub fn float_mod(a: f64, b: f64) -> f64 {
+    let m = a % b;
+    if (m > 0.0 && b < 0.0) || (m < 0.0 && b > 0.0) {
+        m + b
+    } else {
+        m
+    }
+}
+
+/// C `isspace` in the C locale
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r')
+}
+
+fn trim_spaces(mut s: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = s
+        && is_space(*first)
+    {
+        s = rest;
+    }
+    while let [rest @ .., last] = s
+        && is_space(*last)
+    {
+        s = rest;
+    }
+    s
+}
+
+/// Split off a leading `-` or `+`, returning whether it was negative
+fn split_sign(s: &[u8]) -> (bool, &[u8]) {
+    match s {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, s),
+    }
+}
+
+/// Convert a numeral to a number the way the lexer and `tonumber` do.
+/// Surrounding whitespace and a sign are allowed. Hex integers wrap around
+/// and decimal integers that overflow become floats.
+pub fn str_to_number(s: &[u8]) -> Option<Number> {
+    let s = trim_spaces(s);
+    str_to_integer(s)
+        .map(Number::Integer)
+        .or_else(|| str_to_float(s).map(Number::Float))
+}
+
+fn str_to_integer(s: &[u8]) -> Option<i64> {
+    let (neg, s) = split_sign(s);
+    let n = match s {
+        [b'0', b'x' | b'X', digits @ ..] => {
+            if digits.is_empty() || !digits.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            digits.iter().fold(0u64, |n, &d| {
+                n.wrapping_mul(16)
+                    .wrapping_add((d as char).to_digit(16).unwrap() as u64)
+            })
+        }
+        _ => {
+            if s.is_empty() || !s.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            // Only -2^63 may go past i64::MAX
+            let max = i64::MAX as u64 + neg as u64;
+            s.iter().try_fold(0u64, |n, &d| {
+                n.checked_mul(10)
+                    .and_then(|n| n.checked_add((d - b'0') as u64))
+                    .filter(|&n| n <= max)
+            })?
+        }
+    };
+    Some(if neg { n.wrapping_neg() } else { n } as i64)
+}
+
+fn str_to_float(s: &[u8]) -> Option<f64> {
+    // Reject "inf" and "nan", which Rust would accept
+    if s.iter().any(|&b| b == b'n' || b == b'N') {
+        return None;
+    }
+    if s.iter().any(|&b| b == b'x' || b == b'X') {
+        return hex_to_float(s);
+    }
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+/// Hex floats like `0x1.8p3`, `lua_strx2number` in the reference
+fn hex_to_float(s: &[u8]) -> Option<f64> {
+    let (neg, s) = split_sign(s);
+    let [b'0', b'x' | b'X', s @ ..] = s else {
+        return None;
+    };
+    let mut mantissa = 0.0;
+    let mut exp: i64 = 0;
+    let mut significant = 0;
+    let mut any_digit = false;
+    let mut seen_dot = false;
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'.' if !seen_dot => seen_dot = true,
+            d if d.is_ascii_hexdigit() => {
+                any_digit = true;
+                let d = (d as char).to_digit(16).unwrap();
+                if significant == 0 && d == 0 {
+                    // Leading zeros don't count as significant digits
+                    if seen_dot {
+                        exp -= 4;
+                    }
+                } else if significant < 30 {
+                    significant += 1;
+                    mantissa = mantissa * 16.0 + d as f64;
+                    if seen_dot {
+                        exp -= 4;
+                    }
+                } else if !seen_dot {
+                    // Too many digits, keep only the scale
+                    exp += 4;
+                }
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    if !any_digit {
+        return None;
+    }
+    if let Some(b'p' | b'P') = s.get(i) {
+        let (exp_neg, digits) = split_sign(&s[i + 1..]);
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let e = digits
+            .iter()
+            .fold(0i64, |e, &d| (e * 10 + (d - b'0') as i64).min(1 << 20));
+        exp += if exp_neg { -e } else { e };
+    } else if i != s.len() {
+        return None;
+    }
+    let value = ldexp(mantissa, exp);
+    Some(if neg { -value } else { value })
+}
+
+/// `x * 2^exp` without overflowing or flushing to zero halfway through
+fn ldexp(mut x: f64, mut exp: i64) -> f64 {
+    while exp != 0 && x != 0.0 && x.is_finite() {
+        let step = exp.clamp(-1000, 1000);
+        x *= 2f64.powi(step as i32);
+        exp -= step;
+    }
+    x
+}
+
+/// `tonumber(s, base)`: an integer numeral in any base from 2 to 36, with
+/// optional surrounding whitespace and sign. Overflow wraps around.
+pub fn str_to_integer_base(s: &[u8], base: u32) -> Result<Option<i64>> {
+    if !(2..=36).contains(&base) {
+        return Err(Error::BaseOutOfRange);
+    }
+    let (neg, digits) = split_sign(trim_spaces(s));
+    if digits.is_empty() {
+        return Ok(None);
+    }
+    let mut n: u64 = 0;
+    for &d in digits {
+        match (d as char).to_digit(base) {
+            Some(d) => n = n.wrapping_mul(base as u64).wrapping_add(d as u64),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(if neg { n.wrapping_neg() } else { n } as i64))
+}
+
+/// C's `%.{precision}g`
+fn format_g(x: f64, precision: usize) -> String {
+    if x.is_nan() {
+        return if x.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if x.is_infinite() {
+        return if x < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    if x == 0.0 {
+        return if x.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    // The exponent after rounding to `precision` significant digits
+    let sci = format!("{:.*e}", precision - 1, x);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    if exp < -4 || exp >= precision as i32 {
+        let mantissa = strip_fraction_zeros(mantissa);
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exp.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exp) as usize;
+        strip_fraction_zeros(&format!("{:.*}", decimals, x)).to_string()
+    }
+}
+
+fn strip_fraction_zeros(s: &str) -> &str {
+    match s.contains('.') {
+        true => s.trim_end_matches('0').trim_end_matches('.'),
+        false => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floor_semantics() {
+        assert_eq!(int_idiv(7, 2), Ok(3));
+        assert_eq!(int_idiv(-7, 2), Ok(-4));
+        assert_eq!(int_idiv(7, -2), Ok(-4));
+        assert_eq!(int_idiv(i64::MIN, -1), Ok(i64::MIN));
+        assert_eq!(int_mod(-7, 2), Ok(1));
+        assert_eq!(int_mod(7, -2), Ok(-1));
+        assert_eq!(int_mod(i64::MIN, -1), Ok(0));
+        assert_eq!(float_idiv(-7.0, 2.0), -4.0);
+        assert_eq!(float_mod(-7.0, 2.0), 1.0);
+        assert_eq!(float_mod(5.5, -2.0), -0.5);
+        assert_eq!(
+            Number::Integer(7).idiv(Number::Float(2.0)),
+            Ok(Number::Float(3.0))
+        );
+    }
+
+    #[test]
+    fn bitwise_coercion() {
+        let (i, f) = (Number::Integer, Number::Float);
+        assert_eq!(i(6).band(i(3)), Ok(i(2)));
+        assert_eq!(i(6).bor(f(3.0)), Ok(i(7)));
+        assert_eq!(f(6.0).bxor(i(3)), Ok(i(5)));
+        assert_eq!(i(0).bnot(), Ok(i(-1)));
+        assert_eq!(f(1.5).band(i(1)), Err(Error::NoIntegerRep));
+        assert_eq!(f(f64::NAN).bnot(), Err(Error::NoIntegerRep));
+        assert_eq!(f(9223372036854775808.0).bnot(), Err(Error::NoIntegerRep));
+        assert_eq!(
+            Error::NoIntegerRep.to_string(),
+            "number has no integer representation"
+        );
+    }
+
+    #[test]
+    fn shifts() {
+        let i = Number::Integer;
+        assert_eq!(i(1).shiftl(i(4)), Ok(i(16)));
+        assert_eq!(i(1).shiftl(i(63)), Ok(i(i64::MIN)));
+        assert_eq!(i(1).shiftl(i(64)), Ok(i(0)));
+        assert_eq!(i(16).shiftl(i(-4)), Ok(i(1)));
+        assert_eq!(i(-1).shiftr(i(60)), Ok(i(0xf)));
+        assert_eq!(i(-1).shiftr(i(64)), Ok(i(0)));
+        assert_eq!(i(1).shiftr(i(-2)), Ok(i(4)));
+        assert_eq!(i(1).shiftr(i(i64::MIN)), Ok(i(0)));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let err = Number::Integer(1).idiv(Number::Integer(0)).unwrap_err();
+        assert_eq!(err.to_string(), "attempt to perform 'n//0'");
+        let err = Number::Integer(1).modulo(Number::Integer(0)).unwrap_err();
+        assert_eq!(err.to_string(), "attempt to perform 'n%0'");
+
+        assert_eq!(float_idiv(1.0, 0.0), f64::INFINITY);
+        assert_eq!(float_idiv(-1.0, 0.0), f64::NEG_INFINITY);
+        assert!(float_idiv(0.0, 0.0).is_nan());
+        assert!(float_mod(1.0, 0.0).is_nan());
+        assert_eq!(
+            Number::Integer(1).idiv(Number::Float(0.0)),
+            Ok(Number::Float(f64::INFINITY))
+        );
+    }
+
+    #[test]
+    fn tostring_formatting() {
+        let show = |n: Number| n.to_string();
+        assert_eq!(show(Number::Integer(-42)), "-42");
+        assert_eq!(show(Number::Float(1.0)), "1.0");
+        assert_eq!(show(Number::Float(-0.0)), "-0.0");
+        assert_eq!(show(Number::Float(0.1)), "0.1");
+        assert_eq!(show(Number::Float(1.0 / 3.0)), "0.33333333333333");
+        assert_eq!(show(Number::Float(100000000000000.0)), "1e+14");
+        assert_eq!(show(Number::Float(1e15)), "1e+15");
+        assert_eq!(show(Number::Float(12345678901234.0)), "12345678901234.0");
+        assert_eq!(show(Number::Float(2f64.powi(63))), "9.2233720368548e+18");
+        assert_eq!(show(Number::Float(0.0001)), "0.0001");
+        assert_eq!(show(Number::Float(0.00001)), "1e-05");
+        assert_eq!(show(Number::Float(1.5e300)), "1.5e+300");
+        assert_eq!(show(Number::Float(f64::INFINITY)), "inf");
+        assert_eq!(show(Number::Float(f64::NEG_INFINITY)), "-inf");
+        assert_eq!(show(Number::Float(-f64::NAN)), "-nan");
+    }
+
+    #[test]
+    fn numeral_conversion() {
+        let num = |s: &str| str_to_number(s.as_bytes());
+        let (i, f) = (Number::Integer, Number::Float);
+        assert_eq!(num("  10  "), Some(i(10)));
+        assert_eq!(num("-0x10"), Some(i(-16)));
+        assert_eq!(num("0xffffffffffffffff"), Some(i(-1)));
+        assert_eq!(num("0x10000000000000000"), Some(i(0)));
+        assert_eq!(num("9223372036854775807"), Some(i(i64::MAX)));
+        assert_eq!(num("9223372036854775808"), Some(f(9223372036854775808.0)));
+        assert_eq!(num("-9223372036854775808"), Some(i(i64::MIN)));
+        assert_eq!(num("1e2"), Some(f(100.0)));
+        assert_eq!(num(".5"), Some(f(0.5)));
+        assert_eq!(num("5."), Some(f(5.0)));
+        assert_eq!(num("0x1p4"), Some(f(16.0)));
+        assert_eq!(num("0xA.8"), Some(f(10.5)));
+        assert_eq!(num("0x.1P-2"), Some(f(0.015625)));
+        assert_eq!(num("0x1P+1024"), Some(f(f64::INFINITY)));
+        for bad in [
+            "", " ", "1e", "0x", "0x1p", "1 2", "inf", "nan", "1.2.3", "- 1", "0x.p1",
+        ] {
+            assert_eq!(num(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn tonumber_with_base() {
+        let base = |s: &str, b| str_to_integer_base(s.as_bytes(), b);
+        assert_eq!(base("ff", 16), Ok(Some(255)));
+        assert_eq!(base(" -101 ", 2), Ok(Some(-5)));
+        assert_eq!(base("zz", 36), Ok(Some(1295)));
+        assert_eq!(base("Zz", 36), Ok(Some(1295)));
+        assert_eq!(base("8", 8), Ok(None));
+        assert_eq!(base("", 10), Ok(None));
+        assert_eq!(base("1.0", 10), Ok(None));
+        assert_eq!(base("1", 37), Err(Error::BaseOutOfRange));
+        assert_eq!(
+            Error::BaseOutOfRange.to_string(),
+            "bad argument #2 to 'tonumber' (base out of range)"
+        );
+    }
+}
@@ -0,0 +1,644 @@
+//! Binary packing for `string.pack`, `string.unpack` and `string.packsize`,
+//! following the format language of the reference `lstrlib.c`.
+//!
+//! Positions in and out of this module are 0-based byte offsets.
+
+use std::borrow::Cow;
+
+use crate::number::{self, Number};
+use crate::value::Scalar;
+
+/// Largest size accepted for `i`, `I` and `s` options
+const MAX_INT_SIZE: usize = 16;
+/// Size of a Lua integer
+const SZINT: usize = 8;
+/// Alignment `!` uses when given no size
+const NATIVE_MAX_ALIGN: usize = 8;
+/// Format sizes are capped like `MAXSIZE` in the reference
+const MAX_SIZE: usize = i32::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    InvalidOption(char),
+    /// `i`, `I` or `s` with a size outside 1 to 16
+    SizeOutOfLimits(usize),
+    MissingCharSize,
+    /// An integer wider than 8 bytes with significant upper bytes
+    IntegerDoesNotFit(usize),
+    /// An argument error, numbered like the arguments of the Lua function
+    /// (the format is argument 1)
+    BadArgument {
+        arg: usize,
+        func: &'static str,
+        msg: &'static str,
+    },
+    /// An argument of the wrong type, or a missing one
+    WrongType {
+        arg: usize,
+        func: &'static str,
+        expected: &'static str,
+        got: &'static str,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidOption(c) => write!(f, "invalid format option '{}'", c),
+            Error::SizeOutOfLimits(n) => write!(
+                f,
+                "integral size ({}) out of limits [1,{}]",
+                n, MAX_INT_SIZE
+            ),
+            Error::MissingCharSize => write!(f, "missing size for format option 'c'"),
+            Error::IntegerDoesNotFit(n) => {
+                write!(f, "{}-byte integer does not fit into Lua Integer", n)
+            }
+            Error::BadArgument { arg, func, msg } => {
+                write!(f, "bad argument #{} to '{}' ({})", arg, func, msg)
+            }
+            Error::WrongType {
+                arg,
+                func,
+                expected,
+                got,
+            } => write!(
+                f,
+                "bad argument #{} to '{}' ({} expected, got {})",
+                arg, func, expected, got
+            ),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Int,
+    Uint,
+    Float,
+    Double,
+    /// `c`, a fixed-size string
+    Char,
+    /// `s`, a string preceded by its length
+    String,
+    /// `z`, a zero-terminated string
+    Zstr,
+    Padding,
+    PadAlign,
+    Nop,
+}
+
+/// Reads options off a format string, tracking endianness and alignment
+struct Format<'f> {
+    fmt: &'f [u8],
+    pos: usize,
+    little: bool,
+    max_align: usize,
+    /// The Lua function the format was passed to, for argument errors
+    func: &'static str,
+}
+
+impl<'f> Format<'f> {
+    fn new(fmt: &'f [u8], func: &'static str) -> Self {
+        Self {
+            fmt,
+            pos: 0,
+            little: cfg!(target_endian = "little"),
+            max_align: 1,
+            func,
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.fmt.len()
+    }
+
+    /// Read an optional size, `default` if there is none
+    fn num(&mut self, default: usize) -> usize {
+        if !matches!(self.fmt.get(self.pos), Some(b'0'..=b'9')) {
+            return default;
+        }
+        let mut n = 0;
+        while let Some(&d @ b'0'..=b'9') = self.fmt.get(self.pos) {
+            if n > (MAX_SIZE - 9) / 10 {
+                break;
+            }
+            n = n * 10 + (d - b'0') as usize;
+            self.pos += 1;
+        }
+        n
+    }
+
+    fn num_limit(&mut self, default: usize) -> Result<usize> {
+        let n = self.num(default);
+        if n == 0 || n > MAX_INT_SIZE {
+            return Err(Error::SizeOutOfLimits(n));
+        }
+        Ok(n)
+    }
+
+    /// Read the next option and its size
+    fn option(&mut self) -> Result<(Kind, usize)> {
+        let c = self.fmt[self.pos];
+        self.pos += 1;
+        let option = match c {
+            b'b' => (Kind::Int, 1),
+            b'B' => (Kind::Uint, 1),
+            b'h' => (Kind::Int, 2),
+            b'H' => (Kind::Uint, 2),
+            b'l' | b'j' => (Kind::Int, 8),
+            b'L' | b'J' | b'T' => (Kind::Uint, 8),
+            b'f' => (Kind::Float, 4),
+            b'n' | b'd' => (Kind::Double, 8),
+            b'i' => (Kind::Int, self.num_limit(4)?),
+            b'I' => (Kind::Uint, self.num_limit(4)?),
+            b's' => (Kind::String, self.num_limit(8)?),
+            b'c' => match self.fmt.get(self.pos) {
+                Some(b'0'..=b'9') => (Kind::Char, self.num(0)),
+                _ => return Err(Error::MissingCharSize),
+            },
+            b'z' => (Kind::Zstr, 0),
+            b'x' => (Kind::Padding, 1),
+            b'X' => (Kind::PadAlign, 0),
+            b' ' => (Kind::Nop, 0),
+            b'<' => {
+                self.little = true;
+                (Kind::Nop, 0)
+            }
+            b'>' => {
+                self.little = false;
+                (Kind::Nop, 0)
+            }
+            b'=' => {
+                self.little = cfg!(target_endian = "little");
+                (Kind::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.num_limit(NATIVE_MAX_ALIGN)?;
+                (Kind::Nop, 0)
+            }
+            _ => return Err(Error::InvalidOption(c as char)),
+        };
+        Ok(option)
+    }
+
+    /// Read the next option, its size and the padding needed before it
+    /// when `total` bytes precede it
+    fn details(&mut self, total: usize) -> Result<(Kind, usize, usize)> {
+        let (kind, size) = self.option()?;
+        let mut align = size;
+        if kind == Kind::PadAlign {
+            // `X` takes its alignment from the option after it
+            let invalid = bad_argument(1, self.func, "invalid next option for option 'X'");
+            if self.done() {
+                return Err(invalid);
+            }
+            let (next, next_size) = self.option()?;
+            if next == Kind::Char || next_size == 0 {
+                return Err(invalid);
+            }
+            align = next_size;
+        }
+        if align <= 1 || kind == Kind::Char {
+            return Ok((kind, size, 0));
+        }
+        let align = align.min(self.max_align);
+        if !align.is_power_of_two() {
+            return Err(bad_argument(
+                1,
+                self.func,
+                "format asks for alignment not power of 2",
+            ));
+        }
+        Ok((kind, size, (align - (total & (align - 1))) & (align - 1)))
+    }
+}
+
+fn bad_argument(arg: usize, func: &'static str, msg: &'static str) -> Error {
+    Error::BadArgument { arg, func, msg }
+}
+
+/// Serialize `args` according to `fmt`, like `string.pack`
+pub fn pack(fmt: &[u8], args: &[Scalar]) -> Result<Vec<u8>> {
+    let mut format = Format::new(fmt, "pack");
+    let mut out = Vec::new();
+    let mut args = args.iter();
+    let mut arg = 1;
+    while !format.done() {
+        let (kind, size, pad) = format.details(out.len())?;
+        out.resize(out.len() + pad, 0);
+        if matches!(kind, Kind::Padding | Kind::PadAlign | Kind::Nop) {
+            out.resize(out.len() + size, 0);
+            continue;
+        }
+        arg += 1;
+        let value = args.next();
+        match kind {
+            Kind::Int | Kind::Uint => {
+                let n = check_integer(value, arg)?;
+                if size < SZINT {
+                    let fits = if kind == Kind::Int {
+                        let lim = 1i64 << (size * 8 - 1);
+                        -lim <= n && n < lim
+                    } else {
+                        (n as u64) < 1u64 << (size * 8)
+                    };
+                    if !fits {
+                        let msg = match kind {
+                            Kind::Int => "integer overflow",
+                            _ => "unsigned overflow",
+                        };
+                        return Err(bad_argument(arg, "pack", msg));
+                    }
+                }
+                pack_int(
+                    &mut out,
+                    n as u64,
+                    format.little,
+                    size,
+                    kind == Kind::Int && n < 0,
+                );
+            }
+            Kind::Float => {
+                let f = check_number(value, arg)?.to_float() as f32;
+                match format.little {
+                    true => out.extend_from_slice(&f.to_le_bytes()),
+                    false => out.extend_from_slice(&f.to_be_bytes()),
+                }
+            }
+            Kind::Double => {
+                let f = check_number(value, arg)?.to_float();
+                match format.little {
+                    true => out.extend_from_slice(&f.to_le_bytes()),
+                    false => out.extend_from_slice(&f.to_be_bytes()),
+                }
+            }
+            Kind::Char => {
+                let s = check_string(value, arg)?;
+                if s.len() > size {
+                    return Err(bad_argument(arg, "pack", "string longer than given size"));
+                }
+                out.extend_from_slice(&s);
+                out.resize(out.len() + size - s.len(), 0);
+            }
+            Kind::String => {
+                let s = check_string(value, arg)?;
+                if size < 8 && s.len() as u64 >= 1u64 << (size * 8) {
+                    return Err(bad_argument(
+                        arg,
+                        "pack",
+                        "string length does not fit in given size",
+                    ));
+                }
+                pack_int(&mut out, s.len() as u64, format.little, size, false);
+                out.extend_from_slice(&s);
+            }
+            Kind::Zstr => {
+                let s = check_string(value, arg)?;
+                if s.contains(&0) {
+                    return Err(bad_argument(arg, "pack", "string contains zeros"));
+                }
+                out.extend_from_slice(&s);
+                out.push(0);
+            }
+            Kind::Padding | Kind::PadAlign | Kind::Nop => unreachable!(),
+        }
+    }
+    Ok(out)
+}
+
+/// The size of the output of `pack` for `fmt`, like `string.packsize`
+pub fn packsize(fmt: &[u8]) -> Result<usize> {
+    let mut format = Format::new(fmt, "packsize");
+    let mut total: usize = 0;
+    while !format.done() {
+        let (kind, size, pad) = format.details(total)?;
+        if matches!(kind, Kind::String | Kind::Zstr) {
+            return Err(bad_argument(1, "packsize", "variable-length format"));
+        }
+        let size = size + pad;
+        if total > MAX_SIZE - size {
+            return Err(bad_argument(1, "packsize", "format result too large"));
+        }
+        total += size;
+    }
+    Ok(total)
+}
+
+/// Read values from `data` starting at `pos` according to `fmt`, like
+/// `string.unpack`. Returns the values and the position after them.
+pub fn unpack(fmt: &[u8], data: &[u8], mut pos: usize) -> Result<(Vec<Scalar>, usize)> {
+    if pos > data.len() {
+        return Err(bad_argument(3, "unpack", "initial position out of string"));
+    }
+    let mut format = Format::new(fmt, "unpack");
+    let mut values = Vec::new();
+    let too_short = || bad_argument(2, "unpack", "data string too short");
+    while !format.done() {
+        let (kind, size, pad) = format.details(pos)?;
+        if pad + size > data.len() - pos {
+            return Err(too_short());
+        }
+        pos += pad;
+        let bytes = &data[pos..pos + size];
+        match kind {
+            Kind::Int | Kind::Uint => {
+                let n = unpack_int(bytes, format.little, kind == Kind::Int)?;
+                values.push(Scalar::Number(Number::Integer(n)));
+            }
+            Kind::Float => {
+                let bytes = bytes.try_into().unwrap();
+                let f = match format.little {
+                    true => f32::from_le_bytes(bytes),
+                    false => f32::from_be_bytes(bytes),
+                };
+                values.push(Scalar::Number(Number::Float(f as f64)));
+            }
+            Kind::Double => {
+                let bytes = bytes.try_into().unwrap();
+                let f = match format.little {
+                    true => f64::from_le_bytes(bytes),
+                    false => f64::from_be_bytes(bytes),
+                };
+                values.push(Scalar::Number(Number::Float(f)));
+            }
+            Kind::Char => values.push(Scalar::String(bytes.to_vec())),
+            Kind::String => {
+                let len = unpack_int(bytes, format.little, false)? as u64;
+                if len > (data.len() - pos - size) as u64 {
+                    return Err(too_short());
+                }
+                let start = pos + size;
+                values.push(Scalar::String(data[start..start + len as usize].to_vec()));
+                pos += len as usize;
+            }
+            Kind::Zstr => {
+                let Some(len) = data[pos..].iter().position(|&b| b == 0) else {
+                    return Err(bad_argument(
+                        2,
+                        "unpack",
+                        "unfinished string for format 'z'",
+                    ));
+                };
+                values.push(Scalar::String(data[pos..pos + len].to_vec()));
+                pos += len + 1;
+            }
+            Kind::Padding | Kind::PadAlign | Kind::Nop => {}
+        }
+        pos += size;
+    }
+    Ok((values, pos))
+}
+
+fn wrong_type(value: Option<&Scalar>, arg: usize, expected: &'static str) -> Error {
+    Error::WrongType {
+        arg,
+        func: "pack",
+        expected,
+        got: value.map_or("no value", Scalar::type_name),
+    }
+}
+
+/// A number argument, with numeric strings converted like
+/// `luaL_checknumber` does
+fn check_number(value: Option<&Scalar>, arg: usize) -> Result<Number> {
+    value
+        .and_then(Scalar::to_number)
+        .ok_or_else(|| wrong_type(value, arg, "number"))
+}
+
+fn check_integer(value: Option<&Scalar>, arg: usize) -> Result<i64> {
+    match check_number(value, arg)? {
+        Number::Integer(n) => Ok(n),
+        Number::Float(f) => number::float_to_integer(f)
+            .ok_or_else(|| bad_argument(arg, "pack", "number has no integer representation")),
+    }
+}
+
+/// A string argument, with numbers converted like `luaL_checklstring` does
+fn check_string(value: Option<&Scalar>, arg: usize) -> Result<Cow<'_, [u8]>> {
+    value
+        .and_then(Scalar::to_str)
+        .ok_or_else(|| wrong_type(value, arg, "string"))
+}
+
+/// Write the low `size` bytes of `n`, sign-extending past 8 bytes
+fn pack_int(out: &mut Vec<u8>, n: u64, little: bool, size: usize, negative: bool) {
+    let start = out.len();
+    let fill = if negative { 0xff } else { 0 };
+    out.extend((0..size).map(|i| {
+        if i < SZINT {
+            (n >> (i * 8)) as u8
+        } else {
+            fill
+        }
+    }));
+    if !little {
+        out[start..].reverse();
+    }
+}
+
+fn unpack_int(bytes: &[u8], little: bool, signed: bool) -> Result<i64> {
+    let size = bytes.len();
+    let byte = |i: usize| {
+        if little {
+            bytes[i]
+        } else {
+            bytes[size - 1 - i]
+        }
+    };
+    let limit = size.min(SZINT);
+    let mut res: u64 = 0;
+    for i in (0..limit).rev() {
+        res = (res << 8) | byte(i) as u64;
+    }
+    if size < SZINT {
+        if signed {
+            let mask = 1u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > SZINT {
+        // The extra bytes must only be sign extension
+        let fill = if signed && (res as i64) < 0 { 0xff } else { 0 };
+        if (limit..size).any(|i| byte(i) != fill) {
+            return Err(Error::IntegerDoesNotFit(size));
+        }
+    }
+    Ok(res as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> Scalar {
+        Scalar::Number(Number::Integer(n))
+    }
+
+    fn float(f: f64) -> Scalar {
+        Scalar::Number(Number::Float(f))
+    }
+
+    fn string(s: &[u8]) -> Scalar {
+        Scalar::String(s.to_vec())
+    }
+
+    #[test]
+    fn pack_integers() {
+        assert_eq!(pack(b"<i4", &[int(100)]).unwrap(), b"\x64\0\0\0");
+        assert_eq!(pack(b">I2", &[int(258)]).unwrap(), b"\x01\x02");
+        assert_eq!(pack(b"<h", &[int(-2)]).unwrap(), b"\xfe\xff");
+        assert_eq!(pack(b"b", &[float(3.0)]).unwrap(), b"\x03");
+        assert_eq!(pack(b"<i16", &[int(-1)]).unwrap(), [0xff; 16]);
+        // Unsigned values only fill past 8 bytes with zeros
+        let wide = pack(b"<I16", &[int(-1)]).unwrap();
+        assert_eq!(wide, [[0xff; 8], [0; 8]].concat());
+        assert_eq!(unpack(b"<I16", &wide, 0).unwrap(), (vec![int(-1)], 16));
+        let wide = pack(b">i16", &[int(-5)]).unwrap();
+        assert_eq!(unpack(b">i16", &wide, 0).unwrap(), (vec![int(-5)], 16));
+        assert_eq!(pack(b">j", &[int(1)]).unwrap(), b"\0\0\0\0\0\0\0\x01");
+        assert_eq!(
+            pack(b"i1", &[int(200)]).unwrap_err().to_string(),
+            "bad argument #2 to 'pack' (integer overflow)"
+        );
+        assert_eq!(
+            pack(b"I1", &[int(-1)]).unwrap_err().to_string(),
+            "bad argument #2 to 'pack' (unsigned overflow)"
+        );
+        assert_eq!(
+            pack(b"i", &[float(1.5)]).unwrap_err().to_string(),
+            "bad argument #2 to 'pack' (number has no integer representation)"
+        );
+    }
+
+    #[test]
+    fn coercions() {
+        let string = |s: &str| Scalar::String(s.as_bytes().to_vec());
+        assert_eq!(pack(b"<i4", &[string(" 10 ")]).unwrap(), b"\x0a\0\0\0");
+        assert_eq!(pack(b"<i4", &[string("0x10")]).unwrap(), b"\x10\0\0\0");
+        assert_eq!(pack(b"<d", &[string("0.5")]).unwrap(), 0.5f64.to_le_bytes());
+        assert_eq!(pack(b"z", &[int(12)]).unwrap(), b"12\0");
+        assert_eq!(pack(b"z", &[float(1.0)]).unwrap(), b"1.0\0");
+        assert_eq!(pack(b"s1", &[int(-3)]).unwrap(), b"\x02-3");
+        assert_eq!(
+            pack(b"i", &[string("x")]).unwrap_err().to_string(),
+            "bad argument #2 to 'pack' (number expected, got string)"
+        );
+        assert_eq!(
+            pack(b"i", &[string("1.5")]).unwrap_err().to_string(),
+            "bad argument #2 to 'pack' (number has no integer representation)"
+        );
+    }
+
+    #[test]
+    fn pack_strings_and_floats() {
+        assert_eq!(pack(b"z", &[string(b"hi")]).unwrap(), b"hi\0");
+        assert_eq!(pack(b"s1", &[string(b"abc")]).unwrap(), b"\x03abc");
+        assert_eq!(pack(b"c5", &[string(b"ab")]).unwrap(), b"ab\0\0\0");
+        assert_eq!(pack(b"<d", &[int(1)]).unwrap(), 1f64.to_le_bytes());
+        assert_eq!(pack(b">f", &[float(0.5)]).unwrap(), 0.5f32.to_be_bytes());
+        assert_eq!(
+            pack(b"z", &[string(b"a\0b")]).unwrap_err().to_string(),
+            "bad argument #2 to 'pack' (string contains zeros)"
+        );
+        assert_eq!(
+            pack(b"c1", &[string(b"ab")]).unwrap_err().to_string(),
+            "bad argument #2 to 'pack' (string longer than given size)"
+        );
+        assert_eq!(
+            pack(b"i4 i4", &[int(1)]).unwrap_err().to_string(),
+            "bad argument #3 to 'pack' (number expected, got no value)"
+        );
+        assert_eq!(
+            pack(b"z", &[Scalar::Boolean(true)])
+                .unwrap_err()
+                .to_string(),
+            "bad argument #2 to 'pack' (string expected, got boolean)"
+        );
+    }
+
+    #[test]
+    fn alignment_and_sizes() {
+        assert_eq!(
+            pack(b"<!4 b i4", &[int(1), int(2)]).unwrap(),
+            b"\x01\0\0\0\x02\0\0\0"
+        );
+        assert_eq!(pack(b"<b Xi4", &[int(1)]).unwrap(), b"\x01");
+        assert_eq!(pack(b"<!b Xi4 x", &[int(1)]).unwrap(), b"\x01\0\0\0\0");
+        assert_eq!(packsize(b"i4i8").unwrap(), 12);
+        assert_eq!(packsize(b"!i4i8").unwrap(), 16);
+        assert_eq!(packsize(b"!2 b d").unwrap(), 10);
+        assert_eq!(packsize(b"c10 x").unwrap(), 11);
+        assert_eq!(
+            packsize(b"s").unwrap_err().to_string(),
+            "bad argument #1 to 'packsize' (variable-length format)"
+        );
+        assert_eq!(packsize(b"i17"), Err(Error::SizeOutOfLimits(17)));
+        assert_eq!(
+            packsize(b"!3 i4").unwrap_err().to_string(),
+            "bad argument #1 to 'packsize' (format asks for alignment not power of 2)"
+        );
+        assert_eq!(
+            packsize(b"X").unwrap_err().to_string(),
+            "bad argument #1 to 'packsize' (invalid next option for option 'X')"
+        );
+        assert_eq!(
+            pack(b"Xc1", &[]).unwrap_err().to_string(),
+            "bad argument #1 to 'pack' (invalid next option for option 'X')"
+        );
+        assert_eq!(
+            unpack(b"X", b"", 0).unwrap_err().to_string(),
+            "bad argument #1 to 'unpack' (invalid next option for option 'X')"
+        );
+        assert_eq!(packsize(b"c"), Err(Error::MissingCharSize));
+        assert_eq!(packsize(b"y"), Err(Error::InvalidOption('y')));
+    }
+
+    #[test]
+    fn unpack_round_trip() {
+        let fmt = b"<i2 I3 z s2 d c3 j";
+        let values = [
+            int(-1),
+            int(0xabcdef),
+            string(b"zero"),
+            string(b"len"),
+            float(2.5),
+            string(b"xyz"),
+            int(i64::MIN),
+        ];
+        let data = pack(fmt, &values).unwrap();
+        assert_eq!(
+            unpack(fmt, &data, 0).unwrap(),
+            (values.to_vec(), data.len())
+        );
+
+        assert_eq!(
+            unpack(b"<i2", b"\x01\x00\xff\xff", 2).unwrap(),
+            (vec![int(-1)], 4)
+        );
+        assert_eq!(
+            unpack(b"<i16", &[0xff; 16], 0).unwrap(),
+            (vec![int(-1)], 16)
+        );
+        let mut wide = [0u8; 9];
+        wide[8] = 1;
+        assert_eq!(
+            unpack(b"<I9", &wide, 0).unwrap_err().to_string(),
+            "9-byte integer does not fit into Lua Integer"
+        );
+        assert_eq!(
+            unpack(b"i4", b"abc", 0).unwrap_err().to_string(),
+            "bad argument #2 to 'unpack' (data string too short)"
+        );
+        assert_eq!(
+            unpack(b"z", b"abc", 0).unwrap_err().to_string(),
+            "bad argument #2 to 'unpack' (unfinished string for format 'z')"
+        );
+        assert_eq!(
+            unpack(b"b", b"abc", 4).unwrap_err().to_string(),
+            "bad argument #3 to 'unpack' (initial position out of string)"
+        );
+    }
+}
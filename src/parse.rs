@@ -1,44 +1,297 @@
 use crate::ast::*;
-use crate::lex::{Lex, Token};
+use crate::lex::{self, Lex, Token, TokenStream};
 
 #[derive(Debug)]
 pub enum Error {
     SyntaxError(String),
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::SyntaxError(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Longest chunk id, `LUA_IDSIZE` in the reference
+const ID_SIZE: usize = 60;
+
+/// Default nesting limit, `LUAI_MAXCCALLS` in the reference
+const MAX_DEPTH: u32 = 200;
+
+/// How a chunk name shows up in messages, `luaO_chunkid` in the reference.
+/// `@name` is a file name, shortened from the front when too long, `=name`
+/// is used verbatim, and anything else is the source itself, shown as
+/// `[string "..."]` up to its first line break.
+pub fn chunk_id(name: &str) -> String {
+    // Cut at a char boundary at or before `len` bytes
+    let head = |s: &str, mut len: usize| {
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        s[..len].to_string()
+    };
+    if let Some(name) = name.strip_prefix('=') {
+        head(name, name.len().min(ID_SIZE - 1))
+    } else if let Some(name) = name.strip_prefix('@') {
+        if name.len() < ID_SIZE {
+            return name.to_string();
+        }
+        let mut start = name.len() - (ID_SIZE - 4);
+        while !name.is_char_boundary(start) {
+            start += 1;
+        }
+        format!("...{}", &name[start..])
+    } else {
+        const MAX: usize = ID_SIZE - "[string \"...\"]".len() - 1;
+        match name.find('\n') {
+            None if name.len() < MAX => format!("[string \"{}\"]", name),
+            nl => {
+                let line = &name[..nl.unwrap_or(name.len())];
+                format!("[string \"{}...\"]", head(line, line.len().min(MAX)))
+            }
+        }
+    }
+}
+
+/// Where the parser's tokens come from
+enum Source<'a> {
+    Lex(Lex<'a>),
+    /// A recorded stream, with the index of the next lexeme to hand out
+    Tokens(TokenStream<'a>, usize),
+}
+
+impl<'a> Source<'a> {
+    fn next(&mut self) -> lex::Result<Token<'a>> {
+        match self {
+            Source::Lex(lexer) => lexer.next(),
+            Source::Tokens(stream, next) => {
+                // Past the end stands for the Eof handed out
+                *next = (*next + 1).min(stream.lexemes.len() + 1);
+                Ok(stream
+                    .lexemes
+                    .get(*next - 1)
+                    .map_or(Token::Eof, |lexeme| lexeme.token.clone()))
+            }
+        }
+    }
+
+    fn token_span(&self) -> Span {
+        match self {
+            Source::Lex(lexer) => lexer.token_span(),
+            Source::Tokens(stream, next) => match next.checked_sub(1) {
+                Some(i) if i < stream.lexemes.len() => stream.lexemes[i].span,
+                // Eof sits right after the last token
+                _ => {
+                    let end = stream.lexemes.last().map_or(0, |l| l.span.end);
+                    Span::new(end, end)
+                }
+            },
+        }
+    }
+
+    /// The last lexeme handed out, or the last one of all after Eof
+    fn last<'s>(stream: &'s TokenStream<'a>, next: usize) -> Option<&'s lex::Lexeme<'a>> {
+        stream.lexemes[..next.min(stream.lexemes.len())].last()
+    }
+
+    fn line_number(&self) -> u32 {
+        match self {
+            Source::Lex(lexer) => lexer.line_number(),
+            Source::Tokens(stream, next) => Self::last(stream, *next).map_or(1, |l| l.line),
+        }
+    }
+
+    fn line_position(&self) -> usize {
+        match self {
+            Source::Lex(lexer) => lexer.line_position(),
+            Source::Tokens(stream, next) => Self::last(stream, *next).map_or(1, |l| l.column),
+        }
+    }
+
+    /// The source text, which a token stream doesn't have
+    fn text(&self) -> Option<&'a str> {
+        match self {
+            Source::Lex(lexer) => Some(lexer.source()),
+            Source::Tokens(_, _) => None,
+        }
+    }
+
+    fn seek(&mut self, pos: usize) {
+        match self {
+            Source::Lex(lexer) => lexer.seek(pos),
+            Source::Tokens(stream, next) => {
+                *next = stream
+                    .lexemes
+                    .iter()
+                    .position(|l| l.span.start as usize >= pos)
+                    .unwrap_or(stream.lexemes.len());
+            }
+        }
+    }
+}
+
 pub struct Parser<'a> {
-    lexer: Lex<'a>,
+    lexer: Source<'a>,
     current: Token<'a>,
+    /// Byte offsets of `current`
+    current_span: Span,
+    /// Byte offset where the previously consumed token ended
+    prev_end: u32,
+    /// Record `Trivia` on every node
+    lossless: bool,
+    /// `chunk_id` of the chunk name, prefixed to error messages
+    chunk_id: Option<String>,
+    /// Current nesting of statements and expressions
+    depth: u32,
+    max_depth: u32,
+    /// Id for the next node finished
+    next_id: u32,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lex<'a>) -> Self {
-        let current = lexer.next();
-        Self { lexer, current }
+    pub fn new(lexer: Lex<'a>) -> Result<Self> {
+        Self::build(Source::Lex(lexer), None)
+    }
+
+    /// A parser whose errors start with `chunk_id(name)` and the line, like
+    /// `script.lua:3: unexpected symbol near '@'`
+    pub fn with_chunk_name(lexer: Lex<'a>, name: &str) -> Result<Self> {
+        Self::build(Source::Lex(lexer), Some(chunk_id(name)))
+    }
+
+    /// A parser over tokens recorded, and maybe rewritten, beforehand.
+    /// There's no source text to take trivia from, so `lossless` records
+    /// none and `trailing_trivia` is the stream's.
+    pub fn from_tokens(tokens: TokenStream<'a>) -> Result<Self> {
+        Self::build(Source::Tokens(tokens, 0), None)
+    }
+
+    fn build(mut lexer: Source<'a>, chunk_id: Option<String>) -> Result<Self> {
+        let current = Self::lex(&mut lexer, chunk_id.as_deref())?;
+        Ok(Self {
+            current_span: lexer.token_span(),
+            lexer,
+            current,
+            prev_end: 0,
+            lossless: false,
+            chunk_id,
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            next_id: 0,
+        })
+    }
+
+    /// Fail with "chunk has too many syntax levels" past `max_depth` nested
+    /// statements and expressions, instead of overflowing the stack
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Run `parse` one nesting level deeper
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= self.max_depth {
+            return Err(self.error("chunk has too many syntax levels"));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Keep the comments, whitespace and original text of every node, so
+    /// the input can be reproduced exactly with `ast::lossless_source`
+    pub fn lossless(mut self) -> Self {
+        self.lossless = true;
+        self
+    }
+
+    /// Whitespace and comments after the last statement. Only meaningful
+    /// once `parse` has reached the end of the input.
+    pub fn trailing_trivia(&self) -> &'a str {
+        match &self.lexer {
+            Source::Lex(lexer) => &lexer.source()[self.prev_end as usize..],
+            Source::Tokens(stream, _) => stream.trailing,
+        }
+    }
+
+    fn node_id(&mut self) -> NodeId {
+        self.next_id += 1;
+        NodeId(self.next_id - 1)
     }
 
     /// Advance to the next token
-    fn advance(&mut self) {
-        self.current = self.lexer.next();
+    fn advance(&mut self) -> Result<()> {
+        self.prev_end = self.current_span.end;
+        self.current = Self::lex(&mut self.lexer, self.chunk_id.as_deref())?;
+        self.current_span = self.lexer.token_span();
+        Ok(())
+    }
+
+    fn lex(lexer: &mut Source<'a>, chunk_id: Option<&str>) -> Result<Token<'a>> {
+        lexer
+            .next()
+            .map_err(|e: lex::Error| Self::syntax_error(lexer, chunk_id, e))
+    }
+
+    /// An error at the lexer's current position
+    fn syntax_error(lexer: &Source, chunk_id: Option<&str>, msg: impl std::fmt::Display) -> Error {
+        Error::SyntaxError(match chunk_id {
+            Some(id) => format!("{}:{}: {}", id, lexer.line_number(), msg),
+            None => format!(
+                "{} at line {}:{}",
+                msg,
+                lexer.line_number(),
+                lexer.line_position()
+            ),
+        })
+    }
+
+    fn error(&self, msg: impl std::fmt::Display) -> Error {
+        Self::syntax_error(&self.lexer, self.chunk_id.as_deref(), msg)
+    }
+
+    /// Trivia for a node whose tokens span `start..end`, with its leading
+    /// trivia starting at `leading_start`
+    fn trivia(&self, leading_start: u32, start: u32, end: u32) -> Option<Box<Trivia>> {
+        let source = self.lexer.text().filter(|_| self.lossless)?;
+        Some(Box::new(Trivia {
+            leading: source[leading_start as usize..start as usize].to_string(),
+            text: source[start as usize..end as usize].to_string(),
+        }))
     }
 
     fn expect(&mut self, expected: Token<'a>) -> Result<()> {
         if std::mem::discriminant(&self.current) == std::mem::discriminant(&expected) {
-            self.advance();
+            self.advance()?;
             Ok(())
         } else {
-            Err(Error::SyntaxError(format!(
-                "Expected {:?}, got {:?} at line {}:{}",
-                expected,
-                self.current,
-                self.lexer.line_number(),
-                self.lexer.line_position()
+            Err(self.error(format_args!(
+                "{} expected near {}",
+                expected.describe(),
+                self.current.describe()
             )))
         }
     }
 
+    /// Expect `close`, the token matching `open` from line `line`
+    fn expect_match(&mut self, close: Token<'a>, open: Token<'a>, line: u32) -> Result<()> {
+        if self.current == close || line == self.lexer.line_number() {
+            return self.expect(close);
+        }
+        Err(self.error(format_args!(
+            "{} expected (to close {} at line {}) near {}",
+            close.describe(),
+            open.describe(),
+            line,
+            self.current.describe()
+        )))
+    }
+
     pub fn parse(&mut self) -> Result<Vec<StmtNode>> {
         let mut stmts = Vec::new();
         while self.current != Token::Eof {
@@ -47,8 +300,86 @@ impl<'a> Parser<'a> {
         Ok(stmts)
     }
 
+    /// Parse again after `edit` was made to the source `old_chunk` came
+    /// from, reusing the statements the edit can't have affected. The
+    /// parser must be over the edited source and in the same mode that
+    /// produced `old_chunk`.
+    ///
+    /// Statements ending before the edit are kept, except the last of them,
+    /// since the token after a statement decides where it ends. Parsing
+    /// restarts there and stops as soon as it lines up with an old
+    /// statement after the edit again; those are reused with their spans
+    /// shifted by the change in length. Reused nodes keep their ids and new
+    /// ones are numbered after all of `old_chunk`'s.
+    pub fn reparse(
+        &mut self,
+        mut old_chunk: Vec<StmtNode>,
+        edit: &TextEdit,
+    ) -> Result<Vec<StmtNode>> {
+        let delta = edit.text.len() as i64 - (edit.span.end - edit.span.start) as i64;
+        walk(&old_chunk, &mut |node| {
+            if node.id() != NodeId::DUMMY {
+                self.next_id = self.next_id.max(node.id().0 + 1);
+            }
+        });
+        let keep = old_chunk
+            .iter()
+            .take_while(|stmt| stmt.span.end < edit.span.start)
+            .count()
+            .saturating_sub(1);
+        let mut suffix = old_chunk.split_off(keep);
+        let mut stmts = old_chunk;
+
+        // Old statements whose leading trivia also comes after the edit,
+        // with where they'd start in the edited source
+        let mut resync = (1..suffix.len())
+            .filter(|&i| suffix[i - 1].span.end >= edit.span.end)
+            .map(|i| {
+                let shifted = |offset: u32| (offset as i64 + delta) as u32;
+                (
+                    i,
+                    shifted(suffix[i - 1].span.end),
+                    shifted(suffix[i].span.start),
+                )
+            })
+            .peekable();
+
+        self.seek(stmts.last().map_or(0, |stmt| stmt.span.end))?;
+        while self.current != Token::Eof {
+            while resync
+                .next_if(|&(_, _, start)| start < self.current_span.start)
+                .is_some()
+            {}
+            if let Some((i, _, _)) = resync.next_if(|&(_, prev_end, start)| {
+                (prev_end, start) == (self.prev_end, self.current_span.start)
+            }) {
+                let mut rest = suffix.split_off(i);
+                for stmt in &mut rest {
+                    stmt.shift(delta);
+                }
+                stmts.extend(rest);
+                // Leave the parser at the end, as `parse` would
+                self.seek(stmts.last().map_or(0, |stmt| stmt.span.end))?;
+                break;
+            }
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    /// Continue parsing from byte offset `pos`, which must be a token end
+    fn seek(&mut self, pos: u32) -> Result<()> {
+        self.lexer.seek(pos as usize);
+        self.current_span = Span::new(pos, pos);
+        self.advance()
+    }
+
     fn statement(&mut self) -> Result<StmtNode> {
-        let start_span = self.lexer.line_number();
+        self.nested(Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<StmtNode> {
+        let (leading_start, start) = (self.prev_end, self.current_span.start);
 
         let stmt = match self.current {
             // Token::If => self.if_statement(),
@@ -59,7 +390,7 @@ impl<'a> Parser<'a> {
             // Token::Local => self.local_statement(),
             // Token::Return => self.return_statement(),
             Token::Break => {
-                self.advance();
+                self.advance()?;
                 Ok(Stmt::Break)
             }
             _ => {
@@ -72,12 +403,18 @@ impl<'a> Parser<'a> {
             }
         }?;
 
-        let end_span = self.lexer.line_number();
-        Ok(StmtNode::new(stmt, (start_span, end_span)))
+        let mut node = StmtNode::new(stmt, (start, self.prev_end));
+        node.id = self.node_id();
+        node.trivia = self.trivia(leading_start, start, self.prev_end);
+        Ok(node)
     }
 
     fn expression(&mut self) -> Result<ExprNode> {
-        let start_span = self.lexer.line_number();
+        self.nested(Self::expression_inner)
+    }
+
+    fn expression_inner(&mut self) -> Result<ExprNode> {
+        let (leading_start, start) = (self.prev_end, self.current_span.start);
 
         let expr = match self.current {
             Token::False => Expr::Bool(false),
@@ -86,53 +423,284 @@ impl<'a> Parser<'a> {
             Token::Float(f) => Expr::Float(f),
             Token::Name(s) => {
                 let name = s.to_string();
-                self.advance();
+                self.advance()?;
+                let name_end = self.prev_end;
 
-                if self.current == Token::ParL {
-                    self.advance();
+                // The name (and call arguments) are already consumed
+                let expr = if self.current == Token::ParL {
+                    let line = self.lexer.line_number();
+                    self.advance()?;
                     let mut args = Vec::new();
                     if self.current != Token::ParR {
                         loop {
                             args.push(self.expression()?);
                             if self.current == Token::Comma {
-                                self.advance();
+                                self.advance()?;
                             } else {
                                 break;
                             }
                         }
                     }
-                    self.expect(Token::ParR)?;
-                    Expr::FuncCall(
-                        Box::new(ExprNode::new(
-                            Expr::Ident(name),
-                            (start_span, self.lexer.line_number()),
-                        )),
-                        args,
-                    )
+                    self.expect_match(Token::ParR, Token::ParL, line)?;
+                    let mut func = ExprNode::new(Expr::Ident(name), (start, name_end));
+                    func.id = self.node_id();
+                    func.trivia = self.trivia(leading_start, start, name_end);
+                    Expr::FuncCall(Box::new(func), args)
                 } else {
                     Expr::Ident(name)
-                }
+                };
+                let mut node = ExprNode::new(expr, (start, self.prev_end));
+                node.id = self.node_id();
+                node.trivia = self.trivia(leading_start, start, self.prev_end);
+                return Ok(node);
             }
             Token::ParL => {
-                self.advance();
+                let line = self.lexer.line_number();
+                self.advance()?;
                 let inner = self.expression()?;
-                self.expect(Token::ParR)?;
+                self.expect_match(Token::ParR, Token::ParL, line)?;
                 return Ok(inner);
             }
             _ => {
-                return Err(Error::SyntaxError(format!(
-                    "Unexpected token {:?} at line {}:{}",
-                    self.current,
-                    self.lexer.line_number(),
-                    self.lexer.line_position()
+                return Err(self.error(format_args!(
+                    "unexpected symbol near {}",
+                    self.current.describe()
                 )));
             }
         };
 
-        self.advance();
-        let end_span = self.lexer.line_number();
-        Ok(ExprNode::new(expr, (start_span, end_span)))
+        self.advance()?;
+        let mut node = ExprNode::new(expr, (start, self.prev_end));
+        node.id = self.node_id();
+        node.trivia = self.trivia(leading_start, start, self.prev_end);
+        Ok(node)
     }
 
     // TODO: Implement full statement and expression parsing (if, while, repeat, for, functions, etc.)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_call_statements() {
+        let mut parser = Parser::new(Lex::new("print(x, f(1))\nprint()")).unwrap();
+        let stmts = parser.parse().unwrap();
+        assert_eq!(stmts.len(), 2);
+        let Stmt::FuncCall(call) = &stmts[0].stmt else {
+            panic!("expected a call statement, got {:?}", stmts[0].stmt);
+        };
+        let Expr::FuncCall(func, args) = &call.expr else {
+            panic!("expected a call expression, got {:?}", call.expr);
+        };
+        assert!(matches!(&func.expr, Expr::Ident(name) if name == "print"));
+        assert!(matches!(&args[0].expr, Expr::Ident(name) if name == "x"));
+        assert!(matches!(&args[1].expr, Expr::FuncCall(_, inner) if inner.len() == 1));
+    }
+
+    #[test]
+    fn lex_errors_are_syntax_errors() {
+        let err = Parser::new(Lex::new("print(1)\nprint(\"oops)"))
+            .unwrap()
+            .parse()
+            .unwrap_err();
+        let Error::SyntaxError(msg) = err;
+        assert_eq!(msg, "unfinished string at line 2:13");
+    }
+
+    #[test]
+    fn error_messages_name_tokens() {
+        let error = |source: &str| {
+            let Error::SyntaxError(msg) = Parser::with_chunk_name(Lex::new(source), "=t")
+                .unwrap()
+                .parse()
+                .unwrap_err();
+            msg
+        };
+        assert_eq!(error("f(1 x"), "t:1: ')' expected near 'x'");
+        assert_eq!(
+            error("f(1,\n2\nx)"),
+            "t:3: ')' expected (to close '(' at line 1) near 'x'"
+        );
+        assert_eq!(
+            error("print((1\n"),
+            "t:2: ')' expected (to close '(' at line 1) near <eof>"
+        );
+        assert_eq!(error("local x"), "t:1: unexpected symbol near 'local'");
+    }
+
+    #[test]
+    fn parse_token_stream() {
+        let source = "print(VERSION)\n-- done\n";
+        let mut stream = TokenStream::record(&mut Lex::new(source)).unwrap();
+        for lexeme in &mut stream.lexemes {
+            if lexeme.token == Token::Name("VERSION") {
+                lexeme.token = Token::Integer(54);
+            }
+        }
+        let mut parser = Parser::from_tokens(stream).unwrap().lossless();
+        let stmts = parser.parse().unwrap();
+        let Stmt::FuncCall(call) = &stmts[0].stmt else {
+            panic!("expected a call statement, got {:?}", stmts[0].stmt);
+        };
+        let Expr::FuncCall(_, args) = &call.expr else {
+            panic!("expected a call expression, got {:?}", call.expr);
+        };
+        assert!(matches!(args[0].expr, Expr::Integer(54)));
+        assert_eq!(stmts[0].span, Span::new(0, 14));
+        assert_eq!(parser.trailing_trivia(), "\n-- done\n");
+
+        // Dropping tokens leaves errors at the positions they were lexed at
+        let stream: TokenStream = TokenStream::record(&mut Lex::new("f(1,\n2)"))
+            .unwrap()
+            .into_iter()
+            .filter(|lexeme| lexeme.token != Token::Integer(2))
+            .collect();
+        let Error::SyntaxError(msg) = Parser::from_tokens(stream).unwrap().parse().unwrap_err();
+        assert_eq!(msg, "unexpected symbol near ')' at line 2:3");
+    }
+
+    #[test]
+    fn node_ids() {
+        let mut parser = Parser::new(Lex::new("f(x, g(1))\nh()")).unwrap();
+        let stmts = parser.parse().unwrap();
+        let mut ids = Vec::new();
+        walk(&stmts, &mut |node| ids.push(node.id()));
+        assert_eq!(ids.len(), 10);
+        ids.sort();
+        assert_eq!(ids, (0..10).map(NodeId).collect::<Vec<_>>());
+
+        // A side table, here of what kind each node is
+        let mut kinds = NodeMap::new();
+        walk(&stmts, &mut |node| {
+            kinds.insert(node.id(), matches!(node, Node::Stmt(_)));
+        });
+        assert_eq!(kinds.get(stmts[1].id), Some(&true));
+        assert_eq!(kinds.iter().filter(|&(_, &is_stmt)| is_stmt).count(), 2);
+        assert_eq!(kinds.remove(stmts[1].id), Some(true));
+        assert!(!kinds.contains_key(stmts[1].id));
+        assert_eq!(kinds.get(NodeId(100)), None);
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |n: usize| format!("f({}1{})", "(".repeat(n), ")".repeat(n));
+        assert!(Parser::new(Lex::new(&nested(150))).unwrap().parse().is_ok());
+        let source = nested(100_000);
+        let Error::SyntaxError(msg) = Parser::new(Lex::new(&source)).unwrap().parse().unwrap_err();
+        assert_eq!(msg, "chunk has too many syntax levels at line 1:202");
+        let source = nested(10);
+        let mut shallow = Parser::new(Lex::new(&source)).unwrap().max_depth(5);
+        assert!(shallow.parse().is_err());
+    }
+
+    #[test]
+    fn chunk_names() {
+        assert_eq!(chunk_id("@script.lua"), "script.lua");
+        assert_eq!(chunk_id("=stdin"), "stdin");
+        assert_eq!(chunk_id("print(1)"), "[string \"print(1)\"]");
+        assert_eq!(chunk_id("print(1)\nprint(2)"), "[string \"print(1)...\"]");
+        let long = "x".repeat(100);
+        assert_eq!(
+            chunk_id(&format!("@{}", long)),
+            format!("...{}", &long[..56])
+        );
+        assert_eq!(chunk_id(&format!("={}", long)), long[..59]);
+        assert_eq!(chunk_id(&long), format!("[string \"{}...\"]", &long[..45]));
+
+        let err = Parser::with_chunk_name(Lex::new("print(1)\nprint(\"oops)"), "@a.lua")
+            .unwrap()
+            .parse()
+            .unwrap_err();
+        let Error::SyntaxError(msg) = err;
+        assert_eq!(msg, "a.lua:2: unfinished string");
+        let err = Parser::with_chunk_name(Lex::new("@"), "=stdin")
+            .err()
+            .unwrap();
+        let Error::SyntaxError(msg) = err;
+        assert_eq!(msg, "stdin:1: unexpected symbol near '@'");
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let source = "-- header\nprint( 1e3 ,x) --[[ trailing ]]\n\n  f()  -- end\n";
+        let mut parser = Parser::new(Lex::new(source)).unwrap().lossless();
+        let stmts = parser.parse().unwrap();
+        assert_eq!(
+            lossless_source(&stmts, parser.trailing_trivia()).as_deref(),
+            Some(source)
+        );
+
+        let trivia = stmts[1].trivia.as_deref().unwrap();
+        assert_eq!(trivia.leading, " --[[ trailing ]]\n\n  ");
+        assert_eq!(trivia.text, "f()");
+        let Stmt::FuncCall(call) = &stmts[0].stmt else {
+            panic!("expected a call statement, got {:?}", stmts[0].stmt);
+        };
+        let Expr::FuncCall(func, args) = &call.expr else {
+            panic!("expected a call expression, got {:?}", call.expr);
+        };
+        assert_eq!(func.trivia.as_deref().unwrap().leading, "-- header\n");
+        assert_eq!(args[0].trivia.as_deref().unwrap().text, "1e3");
+        assert_eq!(args[1].trivia.as_deref().unwrap().leading, "");
+
+        let mut parser = Parser::new(Lex::new(source)).unwrap();
+        let stmts = parser.parse().unwrap();
+        assert!(stmts[0].trivia.is_none());
+        assert_eq!(lossless_source(&stmts, parser.trailing_trivia()), None);
+    }
+
+    fn reparse(source: &str, edit: TextEdit, mark: usize) -> (String, Vec<StmtNode>) {
+        let mut old = Parser::new(Lex::new(source)).unwrap().parse().unwrap();
+        // Tag an old statement, so the test can tell whether it was reused
+        if let Stmt::FuncCall(call) = &mut old[mark].stmt {
+            call.expr = Expr::Ident("reused".to_string());
+        }
+        let new_source = edit.apply(source);
+        let mut parser = Parser::new(Lex::new(&new_source)).unwrap();
+        let stmts = parser.reparse(old, &edit).unwrap();
+        assert_eq!(parser.current, Token::Eof);
+        (new_source, stmts)
+    }
+
+    fn is_reused(stmt: &StmtNode) -> bool {
+        matches!(&stmt.stmt, Stmt::FuncCall(call) if matches!(&call.expr, Expr::Ident(n) if n == "reused"))
+    }
+
+    #[test]
+    fn reparse_reuses_unaffected_statements() {
+        let source = "f(a)\ng(b)\nh(c)\ni(d)\nj(e)";
+        // Rename `c` to `ccc`, two bytes longer
+        let edit = TextEdit::new(Span::new(12, 13), "ccc");
+
+        let (new_source, stmts) = reparse(source, edit.clone(), 0);
+        assert!(is_reused(&stmts[0]));
+        let (_, stmts) = reparse(source, edit.clone(), 4);
+        assert!(is_reused(&stmts[4]));
+        assert_eq!(stmts[4].span, Span::new(22, 26));
+        let (_, stmts) = reparse(source, edit.clone(), 1);
+        assert!(!is_reused(&stmts[1]));
+
+        let (_, stmts) = reparse(source, edit, 2);
+        let fresh = Parser::new(Lex::new(&new_source)).unwrap().parse().unwrap();
+        assert_eq!(format!("{:?}", &stmts[..1]), format!("{:?}", &fresh[..1]));
+        assert_eq!(stmts[1].span, fresh[1].span);
+        assert_eq!(format!("{:?}", &stmts[3..]), format!("{:?}", &fresh[3..]));
+        assert_eq!(stmts[2].span, Span::new(10, 16));
+        // Reparsed statements can't reuse the ids of the kept ones
+        assert!(stmts[1].id >= NodeId(20) && stmts[2].id >= NodeId(20));
+    }
+
+    #[test]
+    fn reparse_across_statement_boundaries() {
+        let source = "f(a)\ng(b) h(c)\ni(d)";
+        // Join two statements into one call: `g(b, c)`
+        let edit = TextEdit::new(Span::new(8, 12), ", ");
+        let (new_source, stmts) = reparse(source, edit, 3);
+        assert_eq!(new_source, "f(a)\ng(b, c)\ni(d)");
+        assert_eq!(stmts.len(), 3);
+        assert!(is_reused(&stmts[2]));
+        assert_eq!(stmts[2].span, Span::new(13, 17));
+    }
+}
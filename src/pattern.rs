@@ -0,0 +1,995 @@
+//! Lua patterns over byte strings, independent of the VM. This follows the
+//! matcher in the reference `lstrlib.c`, so `string.find`, `string.match`,
+//! `string.gmatch` and `string.gsub` can be thin wrappers around it.
+//!
+//! Offsets in and out of this module are 0-based byte offsets, with match
+//! ends exclusive. Position captures (`()`) are 1-based like in Lua.
+
+const ESC: u8 = b'%';
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+const MAX_CAPTURES: usize = 32;
+/// Recursion limit for the matcher, as MAXCCALLS in the reference
+const MAX_DEPTH: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Pattern ends with a lone `%`
+    EndsWithEscape,
+    /// A `[` set without its closing `]`
+    MissingBracket,
+    /// `%b` without its two delimiters
+    MissingBalanceArgs,
+    /// `%f` not followed by a `[` set
+    MissingFrontierSet,
+    /// A `%1`-`%9` reference to a capture that doesn't exist (yet)
+    InvalidCaptureIndex(usize),
+    /// A `)` without a matching `(`
+    InvalidPatternCapture,
+    /// A `(` that was never closed
+    UnfinishedCapture,
+    TooManyCaptures,
+    TooComplex,
+    /// A `%` in a replacement string not followed by a digit or `%`
+    InvalidReplacement,
+    /// A table or function replacement gave something other than a
+    /// string, number, `nil` or `false`, with the type of what it gave
+    InvalidReplacementValue(&'static str),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::EndsWithEscape => write!(f, "malformed pattern (ends with '%')"),
+            Error::MissingBracket => write!(f, "malformed pattern (missing ']')"),
+            Error::MissingBalanceArgs => {
+                write!(f, "malformed pattern (missing arguments to '%b')")
+            }
+            Error::MissingFrontierSet => write!(f, "missing '[' after '%f' in pattern"),
+            Error::InvalidCaptureIndex(i) => write!(f, "invalid capture index %{}", i),
+            Error::InvalidPatternCapture => write!(f, "invalid pattern capture"),
+            Error::UnfinishedCapture => write!(f, "unfinished capture"),
+            Error::TooManyCaptures => write!(f, "too many captures"),
+            Error::TooComplex => write!(f, "pattern too complex"),
+            Error::InvalidReplacement => {
+                write!(f, "invalid use of '%' in replacement string")
+            }
+            Error::InvalidReplacementValue(kind) => {
+                write!(f, "invalid replacement value (a {})", kind)
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A captured value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture<'a> {
+    /// `(...)`, the captured bytes
+    Str(&'a [u8]),
+    /// `()`, the 1-based position in the subject
+    Position(usize),
+}
+
+/// A successful match of the whole pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    pub start: usize,
+    pub end: usize,
+    /// The pattern's explicit captures, empty if it has none
+    pub captures: Vec<Capture<'a>>,
+}
+
+/// Find the first match of `pattern` in `s`, like `string.find`. Patterns
+/// without special characters are searched for as plain text.
+pub fn find<'a>(s: &'a [u8], pattern: &[u8]) -> Result<Option<Match<'a>>> {
+    Matcher::new().find(s, pattern, 0, false)
+}
+
+/// What `string.match` returns: the captures of the first match, or the
+/// whole match if the pattern has no captures
+pub fn captures<'a>(s: &'a [u8], pattern: &[u8]) -> Result<Option<Vec<Capture<'a>>>> {
+    Matcher::new().captures(s, pattern, 0)
+}
+
+/// The 0-based offset for the `init` argument of `string.find` and
+/// `string.match`: 1-based, counting from the end when negative, and
+/// clamped to the start. `None` when it is past the end of a string of
+/// `len` bytes plus one, where nothing can match.
+pub fn init_offset(init: i64, len: usize) -> Option<usize> {
+    let init = match init {
+        ..0 => (len as u64).saturating_sub(init.unsigned_abs()),
+        _ => init.max(1) as u64 - 1,
+    };
+    (init <= len as u64).then_some(init as usize)
+}
+
+/// Iterate over successive matches like `string.gmatch`, yielding the
+/// captures of each (or the whole match if the pattern has none). A `^`
+/// is not an anchor here, since it would stop the iteration.
+pub fn gmatch<'a, 'p>(s: &'a [u8], pattern: &'p [u8]) -> GMatch<'a, 'p> {
+    Matcher::new().gmatch(s, pattern)
+}
+
+/// Replace every match of `pattern` in `s` like `string.gsub` with a string
+/// replacement, where `%0` is the whole match, `%1`-`%9` are captures and
+/// `%%` is a literal `%`. Returns the result and the number of matches.
+pub fn gsub(s: &[u8], pattern: &[u8], repl: &[u8]) -> Result<(Vec<u8>, usize)> {
+    Matcher::new().gsub(s, pattern, Repl::String(repl), None)
+}
+
+/// What a table or function replacement gives for one match
+pub type Replacement = Result<Option<Vec<u8>>>;
+
+/// What `string.gsub` replaces each match with. Table and function
+/// replacements give `None` for `nil` or `false`, which keeps the match as
+/// it is, and an error stops the substitution.
+pub enum Repl<'r> {
+    /// `%0` is the whole match, `%1`-`%9` are captures and `%%` is `%`
+    String(&'r [u8]),
+    /// A table lookup keyed by the first capture, or the whole match
+    Table(&'r mut dyn FnMut(Capture) -> Replacement),
+    /// A function called with all the captures, or the whole match
+    Function(&'r mut dyn FnMut(&[Capture]) -> Replacement),
+}
+
+/// Settings for matching, which the free functions use the defaults of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Matcher {
+    max_steps: u64,
+}
+
+impl Default for Matcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Matcher {
+    /// No limit on steps, like the reference
+    pub fn new() -> Self {
+        Self {
+            max_steps: u64::MAX,
+        }
+    }
+
+    /// Fail with "pattern too complex" after `max_steps` steps of the
+    /// matcher in one call (or one whole `gmatch` iteration), so untrusted
+    /// patterns that backtrack badly can't run for ages
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    fn state<'a, 'p>(&self, s: &'a [u8], pattern: &'p [u8]) -> MatchState<'a, 'p> {
+        MatchState {
+            steps: self.max_steps,
+            ..MatchState::new(s, pattern)
+        }
+    }
+
+    /// `string.find` from offset `init`. With `plain`, or when it has no
+    /// special characters, the pattern is searched for as plain text.
+    pub fn find<'a>(
+        &self,
+        s: &'a [u8],
+        pattern: &[u8],
+        init: usize,
+        plain: bool,
+    ) -> Result<Option<Match<'a>>> {
+        if init > s.len() {
+            return Ok(None);
+        }
+        if plain || !pattern.iter().any(|b| SPECIALS.contains(b)) {
+            return Ok(find_plain(s, pattern, init).map(|start| Match {
+                start,
+                end: start + pattern.len(),
+                captures: vec![],
+            }));
+        }
+        let mut ms = self.state(s, pattern);
+        match ms.search(init)? {
+            Some((start, end)) => Ok(Some(Match {
+                start,
+                end,
+                captures: ms.captures(start, end, false)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// `string.match` from offset `init`
+    pub fn captures<'a>(
+        &self,
+        s: &'a [u8],
+        pattern: &[u8],
+        init: usize,
+    ) -> Result<Option<Vec<Capture<'a>>>> {
+        if init > s.len() {
+            return Ok(None);
+        }
+        let mut ms = self.state(s, pattern);
+        match ms.search(init)? {
+            Some((start, end)) => ms.captures(start, end, true).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn gmatch<'a, 'p>(&self, s: &'a [u8], pattern: &'p [u8]) -> GMatch<'a, 'p> {
+        GMatch {
+            ms: self.state(s, pattern),
+            pos: 0,
+            last_match: None,
+            done: false,
+        }
+    }
+
+    /// `string.gsub`, replacing at most `max_n` matches if given
+    pub fn gsub(
+        &self,
+        s: &[u8],
+        pattern: &[u8],
+        mut repl: Repl,
+        max_n: Option<usize>,
+    ) -> Result<(Vec<u8>, usize)> {
+        let (anchor, pattern) = match pattern.strip_prefix(b"^") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let mut ms = self.state(s, pattern);
+        let mut out = Vec::with_capacity(s.len());
+        let mut pos = 0;
+        let mut last_match = None;
+        let mut count = 0;
+        while max_n.is_none_or(|max_n| count < max_n) {
+            ms.reset();
+            match ms.do_match(pos, 0)? {
+                Some(end) if Some(end) != last_match => {
+                    count += 1;
+                    add_value(&ms, &mut out, pos, end, &mut repl)?;
+                    pos = end;
+                    last_match = Some(end);
+                }
+                _ if pos < s.len() => {
+                    out.push(s[pos]);
+                    pos += 1;
+                }
+                _ => break,
+            }
+            if anchor {
+                break;
+            }
+        }
+        out.extend_from_slice(&s[pos..]);
+        Ok((out, count))
+    }
+}
+
+pub struct GMatch<'a, 'p> {
+    ms: MatchState<'a, 'p>,
+    pos: usize,
+    last_match: Option<usize>,
+    done: bool,
+}
+
+impl<'a> Iterator for GMatch<'a, '_> {
+    type Item = Result<Vec<Capture<'a>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        while self.pos <= self.ms.src.len() {
+            let start = self.pos;
+            self.ms.reset();
+            match self.ms.do_match(start, 0) {
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                Ok(Some(end)) if Some(end) != self.last_match => {
+                    self.pos = end;
+                    self.last_match = Some(end);
+                    return Some(self.ms.captures(start, end, true));
+                }
+                Ok(_) => self.pos += 1,
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+/// Append the replacement for the match `start..end`
+fn add_value(
+    ms: &MatchState,
+    out: &mut Vec<u8>,
+    start: usize,
+    end: usize,
+    repl: &mut Repl,
+) -> Result<()> {
+    let value = match repl {
+        Repl::String(repl) => return add_string(ms, out, start, end, repl),
+        Repl::Table(lookup) => lookup(ms.capture(0, start, end)?)?,
+        Repl::Function(call) => call(&ms.captures(start, end, true)?)?,
+    };
+    match value {
+        Some(value) => out.extend_from_slice(&value),
+        None => out.extend_from_slice(&ms.src[start..end]),
+    }
+    Ok(())
+}
+
+/// Append `repl` with its `%` references expanded for the match `start..end`
+fn add_string(
+    ms: &MatchState,
+    out: &mut Vec<u8>,
+    start: usize,
+    end: usize,
+    repl: &[u8],
+) -> Result<()> {
+    let mut i = 0;
+    while i < repl.len() {
+        let c = repl[i];
+        i += 1;
+        if c != ESC {
+            out.push(c);
+            continue;
+        }
+        match repl.get(i) {
+            Some(&ESC) => out.push(ESC),
+            Some(&d @ b'0'..=b'9') => match d {
+                b'0' => out.extend_from_slice(&ms.src[start..end]),
+                _ => match ms.capture((d - b'1') as usize, start, end)? {
+                    Capture::Str(bytes) => out.extend_from_slice(bytes),
+                    Capture::Position(p) => out.extend_from_slice(p.to_string().as_bytes()),
+                },
+            },
+            _ => return Err(Error::InvalidReplacement),
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Plain substring search starting at `init`
+fn find_plain(s: &[u8], needle: &[u8], init: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return (init <= s.len()).then_some(init);
+    }
+    s.get(init..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + init)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CaptureLen {
+    Unfinished,
+    Position,
+    Len(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CaptureSlot {
+    start: usize,
+    len: CaptureLen,
+}
+
+struct MatchState<'a, 'p> {
+    src: &'a [u8],
+    pat: &'p [u8],
+    depth: usize,
+    /// Calls to `do_match` left before giving up
+    steps: u64,
+    level: usize,
+    capture: [CaptureSlot; MAX_CAPTURES],
+}
+
+impl<'a, 'p> MatchState<'a, 'p> {
+    fn new(src: &'a [u8], pat: &'p [u8]) -> Self {
+        Self {
+            src,
+            pat,
+            depth: MAX_DEPTH,
+            steps: u64::MAX,
+            level: 0,
+            capture: [CaptureSlot {
+                start: 0,
+                len: CaptureLen::Unfinished,
+            }; MAX_CAPTURES],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.level = 0;
+        self.depth = MAX_DEPTH;
+    }
+
+    /// Try the pattern at each position from `init` on (or only at `init`
+    /// if it is anchored with `^`), returning the first match
+    fn search(&mut self, init: usize) -> Result<Option<(usize, usize)>> {
+        let (anchor, p) = match self.pat.first() {
+            Some(b'^') => (true, 1),
+            _ => (false, 0),
+        };
+        let mut start = init;
+        loop {
+            self.reset();
+            if let Some(end) = self.do_match(start, p)? {
+                return Ok(Some((start, end)));
+            }
+            start += 1;
+            if anchor || start > self.src.len() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn pat_at(&self, p: usize) -> Option<u8> {
+        self.pat.get(p).copied()
+    }
+
+    /// Match the pattern from `p` against the subject from `s`, returning
+    /// where the match ends
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>> {
+        if self.depth == 0 || self.steps == 0 {
+            return Err(Error::TooComplex);
+        }
+        self.depth -= 1;
+        self.steps -= 1;
+        let result = self.match_inner(s, p);
+        self.depth += 1;
+        result
+    }
+
+    fn match_inner(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>> {
+        loop {
+            let Some(c) = self.pat_at(p) else {
+                return Ok(Some(s));
+            };
+            match c {
+                b'(' => {
+                    return if self.pat_at(p + 1) == Some(b')') {
+                        self.start_capture(s, p + 2, CaptureLen::Position)
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unfinished)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => {
+                    return Ok((s == self.src.len()).then_some(s));
+                }
+                ESC if self.pat_at(p + 1) == Some(b'b') => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                ESC if self.pat_at(p + 1) == Some(b'f') => {
+                    p += 2;
+                    if self.pat_at(p) != Some(b'[') {
+                        return Err(Error::MissingFrontierSet);
+                    }
+                    let ep = self.class_end(p)?;
+                    let prev = if s == 0 { 0 } else { self.src[s - 1] };
+                    let cur = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(prev, p, ep - 1)
+                        && self.match_bracket_class(cur, p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                ESC if matches!(self.pat_at(p + 1), Some(b'0'..=b'9')) => {
+                    let l = self.pat[p + 1];
+                    match self.match_capture(s, l)? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let next = self.pat_at(ep);
+                    if !self.single_match(s, p, ep) {
+                        if matches!(next, Some(b'*' | b'?' | b'-')) {
+                            // Accept the empty match
+                            p = ep + 1;
+                            continue;
+                        }
+                        return Ok(None);
+                    }
+                    match next {
+                        Some(b'?') => match self.do_match(s + 1, ep + 1)? {
+                            Some(end) => return Ok(Some(end)),
+                            None => {
+                                p = ep + 1;
+                                continue;
+                            }
+                        },
+                        Some(b'+') => return self.max_expand(s + 1, p, ep),
+                        Some(b'*') => return self.max_expand(s, p, ep),
+                        Some(b'-') => return self.min_expand(s, p, ep),
+                        _ => {
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// End of the single-character class starting at `p`
+    fn class_end(&self, mut p: usize) -> Result<usize> {
+        let c = self.pat[p];
+        p += 1;
+        match c {
+            ESC => {
+                if p >= self.pat.len() {
+                    return Err(Error::EndsWithEscape);
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pat_at(p) == Some(b'^') {
+                    p += 1;
+                }
+                // The first character is part of the set, even if it is `]`
+                loop {
+                    let Some(c) = self.pat_at(p) else {
+                        return Err(Error::MissingBracket);
+                    };
+                    p += 1;
+                    if c == ESC {
+                        if p >= self.pat.len() {
+                            return Err(Error::MissingBracket);
+                        }
+                        p += 1;
+                    }
+                    if self.pat_at(p) == Some(b']') {
+                        return Ok(p + 1);
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else {
+            return false;
+        };
+        match self.pat[p] {
+            b'.' => true,
+            ESC => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    /// Match `c` against the set `[...]` between `p` and its closing `]` at
+    /// `ec`
+    fn match_bracket_class(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let mut sig = true;
+        if self.pat[p + 1] == b'^' {
+            sig = false;
+            p += 1;
+        }
+        p += 1;
+        while p < ec {
+            if self.pat[p] == ESC {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return sig;
+                }
+            } else if self.pat[p + 1] == b'-' && p + 2 < ec {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return sig;
+                }
+                p += 2;
+            } else if self.pat[p] == c {
+                return sig;
+            }
+            p += 1;
+        }
+        !sig
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>> {
+        if p + 1 >= self.pat.len() {
+            return Err(Error::MissingBalanceArgs);
+        }
+        let (open, close) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Greedy repetition: take as many as possible, then back off
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>> {
+        let mut count = 0;
+        while self.single_match(s + count, p, ep) {
+            count += 1;
+        }
+        loop {
+            if let Some(end) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    /// Lazy repetition: take as few as possible
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if !self.single_match(s, p, ep) {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, len: CaptureLen) -> Result<Option<usize>> {
+        if self.level >= MAX_CAPTURES {
+            return Err(Error::TooManyCaptures);
+        }
+        self.capture[self.level] = CaptureSlot { start: s, len };
+        self.level += 1;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>> {
+        let l = (0..self.level)
+            .rev()
+            .find(|&l| matches!(self.capture[l].len, CaptureLen::Unfinished))
+            .ok_or(Error::InvalidPatternCapture)?;
+        self.capture[l].len = CaptureLen::Len(s - self.capture[l].start);
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.capture[l].len = CaptureLen::Unfinished;
+        }
+        Ok(result)
+    }
+
+    /// Match a back reference `%1`-`%9` (`l` is the digit)
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>> {
+        let index = (l as usize).wrapping_sub(b'1' as usize);
+        let slot = match self.capture.get(index) {
+            Some(slot) if index < self.level => slot,
+            _ => return Err(Error::InvalidCaptureIndex(index.wrapping_add(1))),
+        };
+        match slot.len {
+            CaptureLen::Unfinished => Err(Error::InvalidCaptureIndex(index + 1)),
+            // Lua compares a position capture as an (impossibly) huge string
+            CaptureLen::Position => Ok(None),
+            CaptureLen::Len(len) => {
+                let captured = &self.src[slot.start..slot.start + len];
+                Ok(self.src[s..].starts_with(captured).then_some(s + len))
+            }
+        }
+    }
+
+    /// Capture `i` of the match `start..end`; capture 0 of a pattern
+    /// without captures is the whole match
+    fn capture(&self, i: usize, start: usize, end: usize) -> Result<Capture<'a>> {
+        if i >= self.level {
+            return if i == 0 {
+                Ok(Capture::Str(&self.src[start..end]))
+            } else {
+                Err(Error::InvalidCaptureIndex(i + 1))
+            };
+        }
+        let slot = self.capture[i];
+        match slot.len {
+            CaptureLen::Unfinished => Err(Error::UnfinishedCapture),
+            CaptureLen::Position => Ok(Capture::Position(slot.start + 1)),
+            CaptureLen::Len(len) => Ok(Capture::Str(&self.src[slot.start..slot.start + len])),
+        }
+    }
+
+    fn captures(&self, start: usize, end: usize, whole: bool) -> Result<Vec<Capture<'a>>> {
+        let count = if self.level == 0 && whole {
+            1
+        } else {
+            self.level
+        };
+        (0..count).map(|i| self.capture(i, start, end)).collect()
+    }
+}
+
+/// Match `c` against a `%x` class letter, where upper case negates
+fn match_class(c: u8, class: u8) -> bool {
+    let matched = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        // C's isspace also counts \v
+        b's' => c.is_ascii_whitespace() || c == b'\x0b',
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !matched
+    } else {
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs<'a>(captures: &[Capture<'a>]) -> Vec<&'a [u8]> {
+        captures
+            .iter()
+            .map(|c| match c {
+                Capture::Str(s) => *s,
+                Capture::Position(_) => panic!("unexpected position capture"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find_basics() {
+        let m = find(b"hello world", b"o w").unwrap().unwrap();
+        assert_eq!((m.start, m.end), (4, 7));
+        let m = find(b"hello world", b"l+").unwrap().unwrap();
+        assert_eq!((m.start, m.end), (2, 4));
+        let m = find(b"key = value", b"(%w+)%s*=%s*(%w+)").unwrap().unwrap();
+        assert_eq!((m.start, m.end), (0, 11));
+        assert_eq!(strs(&m.captures), [&b"key"[..], b"value"]);
+        assert_eq!(find(b"hello", b"^ell").unwrap(), None);
+        assert_eq!(find(b"hello", b"xyz").unwrap(), None);
+        let m = find(b"", b"").unwrap().unwrap();
+        assert_eq!((m.start, m.end), (0, 0));
+    }
+
+    #[test]
+    fn find_init_and_plain() {
+        let m = Matcher::new();
+        let span = |found: Option<Match>| found.map(|m| (m.start, m.end));
+        assert_eq!(init_offset(1, 3), Some(0));
+        assert_eq!(init_offset(0, 3), Some(0));
+        assert_eq!(init_offset(-1, 3), Some(2));
+        assert_eq!(init_offset(-10, 3), Some(0));
+        assert_eq!(init_offset(4, 3), Some(3));
+        assert_eq!(init_offset(5, 3), None);
+        assert_eq!(init_offset(i64::MIN, 3), Some(0));
+
+        assert_eq!(
+            span(m.find(b"abcabc", b"b", 2, false).unwrap()),
+            Some((4, 5))
+        );
+        assert_eq!(
+            span(m.find(b"abcabc", b"^b", 1, false).unwrap()),
+            Some((1, 2))
+        );
+        assert_eq!(span(m.find(b"a.b", b".", 0, true).unwrap()), Some((1, 2)));
+        assert_eq!(span(m.find(b"a+b", b"+b", 0, true).unwrap()), Some((1, 3)));
+        assert_eq!(span(m.find(b"a%b", b"%", 0, true).unwrap()), Some((1, 2)));
+        // An empty pattern matches at init, even at the very end
+        assert_eq!(span(m.find(b"abc", b"", 3, false).unwrap()), Some((3, 3)));
+        assert_eq!(span(m.find(b"abc", b"", 4, false).unwrap()), None);
+        assert_eq!(m.captures(b"abc", b"()", 4).unwrap(), None);
+
+        let found = m.find(b"k=v", b"(%w)=(%w)", 0, false).unwrap().unwrap();
+        assert_eq!(strs(&found.captures), [b"k", b"v"]);
+        assert_eq!(
+            m.captures(b"a1b2", b"%a(%d)", 1).unwrap().map(|c| strs(&c)),
+            Some(vec![&b"2"[..]])
+        );
+    }
+
+    #[test]
+    fn captures_and_classes() {
+        let c = |s: &'static [u8], p: &[u8]| captures(s, p).unwrap();
+        assert_eq!(strs(&c(b"hello 123", b"%d+").unwrap()), [b"123"]);
+        assert_eq!(strs(&c(b"  trim  ", b"^%s*(.-)%s*$").unwrap()), [b"trim"]);
+        assert_eq!(
+            strs(&c(b"THE (quick) fox", b"%((%a+)%)").unwrap()),
+            [b"quick"]
+        );
+        assert_eq!(strs(&c(b"f(a(b)c)d", b"%b()").unwrap()), [b"(a(b)c)"]);
+        assert_eq!(
+            strs(&c(b"THE (quick) fox", b"%f[%a]%a+%f[%A]").unwrap()),
+            [b"THE"]
+        );
+        assert_eq!(
+            strs(&c(b"x = 'it''s'", b"(['\"])(.-)%1").unwrap()),
+            [&b"'"[..], b"it"]
+        );
+        assert_eq!(strs(&c(b"a-b_c", b"[%w_-]+").unwrap()), [b"a-b_c"]);
+        assert_eq!(strs(&c(b"]]x", b"[]]+").unwrap()), [b"]]"]);
+        assert_eq!(c(b"abc", b"[^%a]"), None);
+        assert_eq!(
+            strs(&c(b"2024-01-15", b"(%d+)-(%d+)-(%d+)").unwrap()).len(),
+            3
+        );
+        assert_eq!(
+            c(b"hello", b"()ll()").unwrap(),
+            [Capture::Position(3), Capture::Position(5)]
+        );
+        assert_eq!(strs(&c(b"aaab", b"a-b").unwrap()), [b"aaab"]);
+        assert_eq!(strs(&c(b"ab", b"ax?b").unwrap()), [b"ab"]);
+        assert_eq!(c(b"abc", b"b$"), None);
+        assert_eq!(strs(&c(b"a$b", b"$b").unwrap()), [b"$b"]);
+    }
+
+    #[test]
+    fn gmatch_and_gsub() {
+        let words: Vec<_> = gmatch(b"one two  three", b"%a+")
+            .map(|c| strs(&c.unwrap())[0])
+            .collect();
+        assert_eq!(words, [&b"one"[..], b"two", b"three"]);
+        let pairs: Vec<_> = gmatch(b"a=1, b=2", b"(%w+)=(%w+)")
+            .map(|c| strs(&c.unwrap()))
+            .collect();
+        assert_eq!(pairs, [[&b"a"[..], b"1"], [&b"b"[..], b"2"]]);
+        // No empty match right where the previous match ended
+        assert_eq!(gmatch(b"abc", b"%a*").count(), 1);
+        assert_eq!(gmatch(b"a,,b", b"[^,]*").count(), 3);
+
+        assert_eq!(
+            gsub(b"hello world", b"o", b"0").unwrap(),
+            (b"hell0 w0rld".to_vec(), 2)
+        );
+        assert_eq!(
+            gsub(b"hello world", b"(%w+)", b"<%1>").unwrap(),
+            (b"<hello> <world>".to_vec(), 2)
+        );
+        assert_eq!(
+            gsub(b"abc", b"%w", b"%0%0").unwrap(),
+            (b"aabbcc".to_vec(), 3)
+        );
+        assert_eq!(gsub(b"abc", b"", b"-").unwrap(), (b"-a-b-c-".to_vec(), 4));
+        assert_eq!(gsub(b"aaa", b"^a", b"b").unwrap(), (b"baa".to_vec(), 1));
+        assert_eq!(gsub(b"50", b"%d+", b"%%").unwrap(), (b"%".to_vec(), 1));
+    }
+
+    #[test]
+    fn gsub_replacements() {
+        let m = Matcher::new();
+        let mut upper = |c: &[Capture]| match c {
+            [Capture::Str(word)] if word != b"keep" => Ok(Some(word.to_ascii_uppercase())),
+            _ => Ok(None),
+        };
+        assert_eq!(
+            m.gsub(b"one keep two", b"%a+", Repl::Function(&mut upper), None)
+                .unwrap(),
+            (b"ONE keep TWO".to_vec(), 3)
+        );
+
+        let mut vars = |c: Capture| match c {
+            Capture::Str(b"name") => Ok(Some(b"Lua".to_vec())),
+            Capture::Str(b"bad") => Err(Error::InvalidReplacementValue("table")),
+            _ => Ok(None),
+        };
+        assert_eq!(
+            m.gsub(b"$name is $other", b"%$(%w+)", Repl::Table(&mut vars), None)
+                .unwrap(),
+            (b"Lua is $other".to_vec(), 2)
+        );
+        assert_eq!(
+            m.gsub(b"$bad", b"%$(%w+)", Repl::Table(&mut vars), None),
+            Err(Error::InvalidReplacementValue("table"))
+        );
+        assert_eq!(
+            Error::InvalidReplacementValue("table").to_string(),
+            "invalid replacement value (a table)"
+        );
+
+        let mut positions = Vec::new();
+        let mut record = |c: &[Capture]| {
+            positions.extend(c.iter().map(|c| match c {
+                Capture::Position(p) => *p,
+                Capture::Str(_) => 0,
+            }));
+            Ok(None)
+        };
+        m.gsub(b"abc", b"()b()", Repl::Function(&mut record), None)
+            .unwrap();
+        assert_eq!(positions, [2, 3]);
+
+        let swap = Repl::String(b"%2=%1");
+        assert_eq!(
+            m.gsub(b"a=1, b=2, c=3", b"(%w+)=(%w+)", swap, Some(2))
+                .unwrap(),
+            (b"1=a, 2=b, c=3".to_vec(), 2)
+        );
+        assert_eq!(
+            m.gsub(b"abc", b"%w", Repl::String(b"x"), Some(0)).unwrap(),
+            (b"abc".to_vec(), 0)
+        );
+        assert_eq!(
+            m.gsub(b"hello hello", b"^hello", Repl::String(b"bye"), None)
+                .unwrap(),
+            (b"bye hello".to_vec(), 1)
+        );
+    }
+
+    #[test]
+    fn step_limit() {
+        let subject = "a".repeat(10000);
+        let limited = Matcher::new().max_steps(100_000);
+        assert_eq!(
+            limited.captures(subject.as_bytes(), b"(a-)-b", 0),
+            Err(Error::TooComplex)
+        );
+        assert_eq!(
+            limited.gsub(subject.as_bytes(), b"a-b", Repl::String(b""), None),
+            Err(Error::TooComplex)
+        );
+        assert_eq!(
+            limited.gmatch(subject.as_bytes(), b"a-b").next(),
+            Some(Err(Error::TooComplex))
+        );
+        // Ordinary matching stays well within it
+        let m = limited
+            .find(subject.as_bytes(), b"a+$", 0, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!((m.start, m.end), (0, 10000));
+        assert_eq!(limited.gmatch(subject.as_bytes(), b"a").count(), 10000);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(find(b"x", b"%"), Err(Error::EndsWithEscape));
+        assert_eq!(find(b"x", b"[a"), Err(Error::MissingBracket));
+        assert_eq!(find(b"x", b"%b("), Err(Error::MissingBalanceArgs));
+        assert_eq!(find(b"x", b"%fx"), Err(Error::MissingFrontierSet));
+        assert_eq!(find(b"x", b"(x"), Err(Error::UnfinishedCapture));
+        assert_eq!(captures(b"x", b"x)"), Err(Error::InvalidPatternCapture));
+        assert_eq!(find(b"xx", b"(x)%2"), Err(Error::InvalidCaptureIndex(2)));
+        assert_eq!(gsub(b"x", b"x", b"%2"), Err(Error::InvalidCaptureIndex(2)));
+        assert_eq!(gsub(b"x", b"x", b"%z"), Err(Error::InvalidReplacement));
+        let deep = "(".repeat(40) + &")".repeat(40);
+        assert_eq!(find(b"x", deep.as_bytes()), Err(Error::TooManyCaptures));
+        let long = "a?".repeat(300);
+        let subject = "a".repeat(300);
+        assert_eq!(
+            find(subject.as_bytes(), long.as_bytes()),
+            Err(Error::TooComplex)
+        );
+        assert_eq!(Error::TooComplex.to_string(), "pattern too complex");
+        assert_eq!(
+            Error::EndsWithEscape.to_string(),
+            "malformed pattern (ends with '%')"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! The `math.random` generator, xoshiro256** seeded and projected the same
+//! way as the reference `lmathlib.c`, so seeded sequences match.
+
+use crate::number::Number;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// `random(m, n)` with `m > n`
+    IntervalEmpty,
+    WrongNumberOfArgs,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::IntervalEmpty => write!(f, "bad argument #1 to 'random' (interval is empty)"),
+            Error::WrongNumberOfArgs => write!(f, "wrong number of arguments"),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+    /// A generator seeded like `math.randomseed(n1, n2)`
+    pub fn new(n1: i64, n2: i64) -> Self {
+        let mut random = Self { state: [0; 4] };
+        random.seed(n1, n2);
+        random
+    }
+
+    /// Reseed like `math.randomseed(n1, n2)`, returning the two seed values
+    pub fn seed(&mut self, n1: i64, n2: i64) -> (i64, i64) {
+        // The 0xff keeps the state from ever being all zeros
+        self.state = [n1 as u64, 0xff, n2 as u64, 0];
+        // Discard some values to spread the seed through the state
+        for _ in 0..16 {
+            self.next_u64();
+        }
+        (n1, n2)
+    }
+
+    /// Reseed from the clock and an address, like `math.randomseed()`
+    pub fn seed_randomly(&mut self) -> (i64, i64) {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let address = self as *const Self as usize as i64;
+        self.seed(time, address)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// A float in `[0, 1)` built from the top 53 bits
+    pub fn float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (0.5f64).powi(53)
+    }
+
+    /// An integer in `[low, up]`
+    pub fn integer(&mut self, low: i64, up: i64) -> Result<i64> {
+        if low > up {
+            return Err(Error::IntervalEmpty);
+        }
+        let rv = self.next_u64();
+        let n = (up as u64).wrapping_sub(low as u64);
+        Ok(self.project(rv, n).wrapping_add(low as u64) as i64)
+    }
+
+    /// `math.random` with its integer arguments: no arguments gives a
+    /// float, a single 0 gives all 64 random bits as an integer
+    pub fn random(&mut self, args: &[i64]) -> Result<Number> {
+        match *args {
+            [] => Ok(Number::Float(self.float())),
+            [0] => Ok(Number::Integer(self.next_u64() as i64)),
+            [up] => self.integer(1, up).map(Number::Integer),
+            [low, up] => self.integer(low, up).map(Number::Integer),
+            _ => Err(Error::WrongNumberOfArgs),
+        }
+    }
+
+    /// Project `ran` into `[0, n]` without bias, drawing again while the
+    /// masked value is out of range
+    fn project(&mut self, mut ran: u64, n: u64) -> u64 {
+        if n & n.wrapping_add(1) == 0 {
+            return ran & n;
+        }
+        // The smallest 2^b - 1 not smaller than n
+        let lim = u64::MAX >> n.leading_zeros();
+        loop {
+            ran &= lim;
+            if ran <= n {
+                return ran;
+            }
+            ran = self.next_u64();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xoshiro_reference_outputs() {
+        let mut random = Random {
+            state: [1, 2, 3, 4],
+        };
+        let outputs: Vec<u64> = (0..4).map(|_| random.next_u64()).collect();
+        assert_eq!(outputs, [11520, 0, 1509978240, 1215971899390074240]);
+    }
+
+    #[test]
+    fn seeded_sequences_repeat() {
+        let mut a = Random::new(42, 0);
+        let mut b = Random::new(1, 0);
+        assert_eq!(b.seed(42, 0), (42, 0));
+        for _ in 0..100 {
+            assert_eq!(a.random(&[0]), b.random(&[0]));
+        }
+        assert_ne!(Random::new(42, 1).next_u64(), Random::new(42, 0).next_u64());
+    }
+
+    #[test]
+    fn ranges() {
+        let mut random = Random::new(7, 0);
+        for _ in 0..1000 {
+            let Number::Float(f) = random.random(&[]).unwrap() else {
+                panic!("expected a float");
+            };
+            assert!((0.0..1.0).contains(&f));
+            let Number::Integer(i) = random.random(&[-3, 5]).unwrap() else {
+                panic!("expected an integer");
+            };
+            assert!((-3..=5).contains(&i));
+            assert_eq!(random.random(&[1]), Ok(Number::Integer(1)));
+        }
+        assert!(random.integer(i64::MIN, i64::MAX).is_ok());
+        assert_eq!(random.random(&[2, 1]), Err(Error::IntervalEmpty));
+        assert_eq!(random.random(&[-1]), Err(Error::IntervalEmpty));
+        assert_eq!(random.random(&[1, 2, 3]), Err(Error::WrongNumberOfArgs));
+    }
+}
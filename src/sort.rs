@@ -0,0 +1,298 @@
+//! `table.sort`, the quicksort of the reference `ltablib.c` with its checks
+//! for inconsistent comparators, plus a stable merge sort.
+//!
+//! Comparators may fail (a Lua `__lt` or order function can raise an
+//! error), so they return a `Result` and the first error stops the sort.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error<E> {
+    /// The comparator isn't a strict order, e.g. `a <= b`
+    InvalidOrder,
+    TooBig,
+    /// The comparator itself failed
+    Compare(E),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidOrder => write!(f, "invalid order function for sorting"),
+            Error::TooBig => write!(f, "bad argument #1 to 'sort' (array too big)"),
+            Error::Compare(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub type Result<T, E> = std::result::Result<T, Error<E>>;
+
+/// Partitions shorter than this fraction of the range they came from
+/// are a sign of adversarial input
+const IMBALANCE: usize = 128;
+
+/// Sort `v` in place with `lt` as the less-than test. Not stable.
+///
+/// Where the reference picks a random pivot after a badly unbalanced
+/// partition, this falls back to heapsort for that range, which keeps
+/// the worst case at O(n log n) and the output deterministic.
+pub fn sort<T, E>(
+    v: &mut [T],
+    mut lt: impl FnMut(&T, &T) -> std::result::Result<bool, E>,
+) -> Result<(), E> {
+    if v.len() >= i32::MAX as usize {
+        return Err(Error::TooBig);
+    }
+    let mut lt = |a: &T, b: &T| lt(a, b).map_err(Error::Compare);
+    if v.len() > 1 {
+        quicksort(v, 0, v.len() - 1, &mut lt)?;
+    }
+    Ok(())
+}
+
+fn quicksort<T, E>(
+    v: &mut [T],
+    mut lo: usize,
+    mut up: usize,
+    lt: &mut impl FnMut(&T, &T) -> Result<bool, E>,
+) -> Result<(), E> {
+    while lo < up {
+        // Sort v[lo], v[p] and v[up], leaving the median of the three at p
+        if lt(&v[up], &v[lo])? {
+            v.swap(lo, up);
+        }
+        if up - lo == 1 {
+            break;
+        }
+        let p = lo + (up - lo) / 2;
+        if lt(&v[p], &v[lo])? {
+            v.swap(p, lo);
+        } else if lt(&v[up], &v[p])? {
+            v.swap(p, up);
+        }
+        if up - lo == 2 {
+            break;
+        }
+        // Park the pivot at up - 1 and partition around it
+        v.swap(p, up - 1);
+        let p = partition(v, lo, up, lt)?;
+        // Recurse into the smaller side and loop on the larger one
+        let smaller;
+        if p - lo < up - p {
+            if p > lo {
+                quicksort(v, lo, p - 1, lt)?;
+            }
+            smaller = p - lo;
+            lo = p + 1;
+        } else {
+            quicksort(v, p + 1, up, lt)?;
+            smaller = up - p;
+            up = p - 1;
+        }
+        if lo < up && (up - lo) / IMBALANCE > smaller {
+            return heapsort(&mut v[lo..=up], lt);
+        }
+    }
+    Ok(())
+}
+
+/// Partition `v[lo..=up]` around the pivot at `up - 1`, returning where the
+/// pivot ends up. `v[lo]` and `v[up]` are already on the right sides.
+fn partition<T, E>(
+    v: &mut [T],
+    lo: usize,
+    up: usize,
+    lt: &mut impl FnMut(&T, &T) -> Result<bool, E>,
+) -> Result<usize, E> {
+    let pivot = up - 1;
+    let mut i = lo;
+    let mut j = up - 1;
+    loop {
+        i += 1;
+        while lt(&v[i], &v[pivot])? {
+            // v[i] < P but v[up - 1] == P
+            if i == up - 1 {
+                return Err(Error::InvalidOrder);
+            }
+            i += 1;
+        }
+        j -= 1;
+        while lt(&v[pivot], &v[j])? {
+            // j < i but v[j] > P
+            if j < i {
+                return Err(Error::InvalidOrder);
+            }
+            j -= 1;
+        }
+        if j < i {
+            v.swap(up - 1, i);
+            return Ok(i);
+        }
+        v.swap(i, j);
+    }
+}
+
+fn heapsort<T, E>(v: &mut [T], lt: &mut impl FnMut(&T, &T) -> Result<bool, E>) -> Result<(), E> {
+    for start in (0..v.len() / 2).rev() {
+        sift_down(v, start, v.len(), lt)?;
+    }
+    for end in (1..v.len()).rev() {
+        v.swap(0, end);
+        sift_down(v, 0, end, lt)?;
+    }
+    Ok(())
+}
+
+fn sift_down<T, E>(
+    v: &mut [T],
+    mut root: usize,
+    end: usize,
+    lt: &mut impl FnMut(&T, &T) -> Result<bool, E>,
+) -> Result<(), E> {
+    loop {
+        let mut child = 2 * root + 1;
+        if child >= end {
+            return Ok(());
+        }
+        if child + 1 < end && lt(&v[child], &v[child + 1])? {
+            child += 1;
+        }
+        if !lt(&v[root], &v[child])? {
+            return Ok(());
+        }
+        v.swap(root, child);
+        root = child;
+    }
+}
+
+/// Sort `v` in place keeping equal elements in their original order, for
+/// `table.sort(t, lt, true)`. Merge sorts a permutation and then applies
+/// it, so it needs no clones and no scratch space the size of `T`.
+pub fn stable_sort<T, E>(
+    v: &mut [T],
+    mut lt: impl FnMut(&T, &T) -> std::result::Result<bool, E>,
+) -> Result<(), E> {
+    if v.len() >= i32::MAX as usize {
+        return Err(Error::TooBig);
+    }
+    let mut order: Vec<usize> = (0..v.len()).collect();
+    let mut scratch = order.clone();
+    let mut width = 1;
+    while width < v.len() {
+        for start in (0..v.len()).step_by(2 * width) {
+            let mid = (start + width).min(v.len());
+            let end = (start + 2 * width).min(v.len());
+            let (mut a, mut b) = (start, mid);
+            for slot in &mut scratch[start..end] {
+                // Take from the right run only when strictly smaller
+                let take_b = a == mid
+                    || (b < end && lt(&v[order[b]], &v[order[a]]).map_err(Error::Compare)?);
+                if take_b {
+                    *slot = order[b];
+                    b += 1;
+                } else {
+                    *slot = order[a];
+                    a += 1;
+                }
+            }
+        }
+        std::mem::swap(&mut order, &mut scratch);
+        width *= 2;
+    }
+    // order[k] is the index of the element that belongs at k
+    for start in 0..order.len() {
+        let mut k = start;
+        while order[k] != start {
+            let next = order[k];
+            v.swap(k, next);
+            order[k] = k;
+            k = next;
+        }
+        order[k] = k;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn less(a: &i64, b: &i64) -> std::result::Result<bool, ()> {
+        Ok(a < b)
+    }
+
+    /// A small deterministic generator for test data
+    fn shuffled(n: usize) -> Vec<i64> {
+        let mut x: u64 = 0x2545f4914f6cdd1d;
+        (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                (x % 1000) as i64
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sorts() {
+        for n in [0, 1, 2, 3, 4, 10, 100, 1000] {
+            let mut v = shuffled(n);
+            let mut expected = v.clone();
+            expected.sort();
+            sort(&mut v, less).unwrap();
+            assert_eq!(v, expected);
+        }
+        let mut v: Vec<i64> = (0..500).rev().collect();
+        sort(&mut v, |a, b| less(b, a)).unwrap();
+        assert_eq!(v, (0..500).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn invalid_order_and_comparator_errors() {
+        let mut v = vec![5; 200];
+        assert_eq!(
+            sort(&mut v, |a, b| Ok::<_, ()>(a <= b)),
+            Err(Error::InvalidOrder)
+        );
+        assert_eq!(
+            Error::<String>::InvalidOrder.to_string(),
+            "invalid order function for sorting"
+        );
+
+        let mut v = shuffled(50);
+        let mut calls = 0;
+        let result = sort(&mut v, |a, b| {
+            calls += 1;
+            if calls == 10 {
+                Err("attempt to compare")
+            } else {
+                Ok(a < b)
+            }
+        });
+        assert_eq!(result, Err(Error::Compare("attempt to compare")));
+    }
+
+    #[test]
+    fn adversarial_input_stays_fast() {
+        // An "organ pipe" that defeats median-of-three pivots
+        let n = 20000;
+        let mut v: Vec<i64> = (0..n / 2).chain((0..n / 2).rev()).collect();
+        let mut calls = 0u64;
+        sort(&mut v, |a, b| {
+            calls += 1;
+            less(a, b)
+        })
+        .unwrap();
+        assert!(v.windows(2).all(|w| w[0] <= w[1]));
+        assert!(calls < 50 * n as u64, "{} comparisons", calls);
+    }
+
+    #[test]
+    fn stable() {
+        let keys = shuffled(300);
+        let mut v: Vec<(i64, usize)> = keys.iter().map(|k| k % 10).zip(0..).collect();
+        let mut expected = v.clone();
+        expected.sort_by_key(|&(k, _)| k);
+        stable_sort(&mut v, |a, b| Ok::<_, ()>(a.0 < b.0)).unwrap();
+        assert_eq!(v, expected);
+    }
+}
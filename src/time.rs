@@ -0,0 +1,176 @@
+//! `os.clock` and a `time` library of monotonic timestamps, sleeping and
+//! timing, for benchmarks and game loops.
+
+use std::sync::{
+    Arc, OnceLock,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A negative or NaN number of seconds
+    InvalidDuration,
+    /// More seconds than a `Duration` holds
+    DurationTooLong,
+    /// The sleep was cut short by its `Interrupt`
+    Interrupted,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidDuration => write!(
+                f,
+                "bad argument #1 to 'sleep' (non-negative number expected)"
+            ),
+            Error::DurationTooLong => {
+                write!(f, "bad argument #1 to 'sleep' (duration too long)")
+            }
+            Error::Interrupted => write!(f, "sleep interrupted"),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Longest stretch a `sleep` goes without checking its interrupt flag
+const POLL: Duration = Duration::from_millis(10);
+
+/// `os.clock`: CPU time used by the process, in seconds
+#[cfg(unix)]
+pub fn clock() -> f64 {
+    // POSIX fixes CLOCKS_PER_SEC at a million
+    const CLOCKS_PER_SEC: f64 = 1_000_000.0;
+    unsafe extern "C" {
+        fn clock() -> std::ffi::c_long;
+    }
+    // SAFETY: clock has no preconditions
+    unsafe { clock() as f64 / CLOCKS_PER_SEC }
+}
+
+/// `os.clock`, approximated by the time since it was first called where
+/// there's no C `clock` to ask
+#[cfg(not(unix))]
+pub fn clock() -> f64 {
+    monotonic_ns() as f64 / 1e9
+}
+
+/// `time.now`: nanoseconds since a fixed point early in the process. Only
+/// differences mean anything, and they never go backwards.
+pub fn monotonic_ns() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+/// What can end a `sleep` early: a deadline, such as the VM's, and a flag
+/// set from another thread
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt {
+    pub deadline: Option<Instant>,
+    pub flag: Option<Arc<AtomicBool>>,
+}
+
+impl Interrupt {
+    fn is_set(&self) -> bool {
+        self.flag
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+}
+
+/// `time.sleep(seconds)`, failing with `Interrupted` as soon as
+/// `interrupt` says so rather than sleeping past it. A sleep too long to
+/// have an end lasts until it is interrupted.
+pub fn sleep(seconds: f64, interrupt: &Interrupt) -> Result<()> {
+    if seconds.is_nan() || seconds < 0.0 {
+        return Err(Error::InvalidDuration);
+    }
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|_| Error::DurationTooLong)?;
+    let end = Instant::now().checked_add(duration);
+    // The deadline only cuts the sleep short if it comes first
+    let deadline = interrupt
+        .deadline
+        .filter(|&deadline| end.is_none_or(|end| deadline < end));
+    loop {
+        if interrupt.is_set() {
+            return Err(Error::Interrupted);
+        }
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| deadline <= now) {
+            return Err(Error::Interrupted);
+        }
+        if end.is_some_and(|end| end <= now) {
+            return Ok(());
+        }
+        let step = deadline
+            .or(end)
+            .map_or(Duration::MAX, |until| until.saturating_duration_since(now));
+        std::thread::sleep(match interrupt.flag {
+            Some(_) => step.min(POLL),
+            None => step,
+        });
+    }
+}
+
+/// `time.measure(f)`: what `f` returns and how many seconds it took
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, f64) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed().as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clocks_move_forward() {
+        let (cpu, ns) = (clock(), monotonic_ns());
+        // Burn some CPU time
+        let (sum, elapsed) = measure(|| (0..2_000_000u64).map(std::hint::black_box).sum::<u64>());
+        assert_eq!(sum, 1_999_999_000_000);
+        assert!(clock() > cpu);
+        assert!(monotonic_ns() > ns);
+        assert!(elapsed > 0.0);
+    }
+
+    #[test]
+    fn sleeping() {
+        let none = Interrupt::default();
+        let (result, elapsed) = measure(|| sleep(0.02, &none));
+        assert_eq!(result, Ok(()));
+        assert!(elapsed >= 0.02);
+        assert_eq!(sleep(-1.0, &none), Err(Error::InvalidDuration));
+        assert_eq!(sleep(f64::NAN, &none), Err(Error::InvalidDuration));
+        assert_eq!(sleep(f64::INFINITY, &none), Err(Error::DurationTooLong));
+
+        let soon = Interrupt {
+            deadline: Some(Instant::now() + Duration::from_millis(10)),
+            flag: None,
+        };
+        let (result, elapsed) = measure(|| sleep(10.0, &soon));
+        assert_eq!(result, Err(Error::Interrupted));
+        assert!(elapsed < 1.0);
+        // Past the end of time, so only the deadline can end it
+        let soon = Interrupt {
+            deadline: Some(Instant::now() + Duration::from_millis(10)),
+            flag: None,
+        };
+        assert_eq!(sleep(1e19, &soon), Err(Error::Interrupted));
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let interrupt = Interrupt {
+            deadline: None,
+            flag: Some(flag.clone()),
+        };
+        let waker = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let (result, elapsed) = measure(|| sleep(10.0, &interrupt));
+        waker.join().unwrap();
+        assert_eq!(result, Err(Error::Interrupted));
+        assert!(elapsed < 1.0);
+    }
+}
@@ -0,0 +1,106 @@
+//! The values the standalone libraries pass in and out, until there is a
+//! VM with a value type of its own, and the conversions between them that
+//! the reference auxiliary library does.
+
+use std::borrow::Cow;
+
+use crate::number::{self, Number};
+
+/// A Lua value other than a table or a function
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scalar {
+    Nil,
+    Boolean(bool),
+    Number(Number),
+    String(Vec<u8>),
+}
+
+impl Scalar {
+    /// The name `type` gives
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Scalar::Nil => "nil",
+            Scalar::Boolean(_) => "boolean",
+            Scalar::Number(_) => "number",
+            Scalar::String(_) => "string",
+        }
+    }
+
+    /// The number this converts to, with numeric strings converted like
+    /// `lua_tonumber` does
+    pub fn to_number(&self) -> Option<Number> {
+        match self {
+            Scalar::Number(n) => Some(*n),
+            Scalar::String(s) => number::str_to_number(s),
+            Scalar::Nil | Scalar::Boolean(_) => None,
+        }
+    }
+
+    /// The string this converts to, with numbers formatted like
+    /// `lua_tolstring` does
+    pub fn to_str(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Scalar::String(s) => Some(Cow::Borrowed(s)),
+            Scalar::Number(n) => Some(Cow::Owned(n.to_string().into_bytes())),
+            Scalar::Nil | Scalar::Boolean(_) => None,
+        }
+    }
+
+    /// `tostring`
+    pub fn to_display(&self) -> Cow<'_, [u8]> {
+        match self {
+            Scalar::Nil => Cow::Borrowed(b"nil"),
+            Scalar::Boolean(true) => Cow::Borrowed(b"true"),
+            Scalar::Boolean(false) => Cow::Borrowed(b"false"),
+            Scalar::Number(_) | Scalar::String(_) => self.to_str().unwrap(),
+        }
+    }
+}
+
+/// Write `s` in double quotes so that Lua reads it back the same, escaping
+/// like `addquoted` in the reference `lstrlib.c`
+pub fn quote(out: &mut Vec<u8>, s: &[u8]) {
+    out.push(b'"');
+    for (i, &b) in s.iter().enumerate() {
+        match b {
+            b'"' | b'\\' | b'\n' => out.extend_from_slice(&[b'\\', b]),
+            0..0x20 | 0x7f => {
+                // A digit after the escape would be read as part of it
+                let escape = match s.get(i + 1) {
+                    Some(next) if next.is_ascii_digit() => format!("\\{:03}", b),
+                    _ => format!("\\{}", b),
+                };
+                out.extend_from_slice(escape.as_bytes());
+            }
+            _ => out.push(b),
+        }
+    }
+    out.push(b'"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let string = |s: &str| Scalar::String(s.as_bytes().to_vec());
+        assert_eq!(string(" 0x10 ").to_number(), Some(Number::Integer(16)));
+        assert_eq!(string("x").to_number(), None);
+        assert_eq!(Scalar::Boolean(true).to_number(), None);
+        assert_eq!(
+            Scalar::Number(Number::Float(2.0)).to_str().as_deref(),
+            Some(&b"2.0"[..])
+        );
+        assert_eq!(Scalar::Nil.to_str(), None);
+        assert_eq!(&*Scalar::Boolean(false).to_display(), b"false");
+        assert_eq!(Scalar::Nil.type_name(), "nil");
+    }
+
+    #[test]
+    fn quoting() {
+        let mut out = Vec::new();
+        quote(&mut out, b"a\"b\\\n\r1\x00\tz");
+        assert_eq!(out, b"\"a\\\"b\\\\\\\n\\0131\\0\\9z\"");
+    }
+}
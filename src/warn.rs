@@ -0,0 +1,159 @@
+//! The warning system behind `warn(...)` and `lua_warning`, following the
+//! state machine of the reference `lauxlib.c`: warnings start off, the
+//! control messages `@on` and `@off` toggle them, and a message can be
+//! built from several pieces.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// `warn()` with no arguments
+    NoArguments,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoArguments => {
+                write!(
+                    f,
+                    "bad argument #1 to 'warn' (string expected, got no value)"
+                )
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Off,
+    On,
+    /// In the middle of a message made of several pieces
+    Continued,
+}
+
+/// Receives each complete warning message
+pub type Handler = Box<dyn FnMut(&[u8])>;
+
+pub struct Warnings {
+    state: State,
+    message: Vec<u8>,
+    handler: Handler,
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Warnings {
+    /// Warnings that are off and print to stderr once turned on
+    pub fn new() -> Self {
+        Self {
+            state: State::Off,
+            message: Vec::new(),
+            handler: Box::new(|message| {
+                eprintln!("Lua warning: {}", String::from_utf8_lossy(message))
+            }),
+        }
+    }
+
+    /// Route warnings somewhere other than stderr
+    pub fn set_handler(&mut self, handler: impl FnMut(&[u8]) + 'static) {
+        self.handler = Box::new(handler);
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.state != State::Off
+    }
+
+    /// Emit one piece of a warning, `to_continue` when more pieces of the
+    /// same message follow
+    pub fn warning(&mut self, piece: &[u8], to_continue: bool) {
+        let state = self.state;
+        if state != State::Continued && self.control(piece, to_continue) {
+            return;
+        }
+        match state {
+            State::Off => {}
+            State::On | State::Continued => {
+                self.message.extend_from_slice(piece);
+                if to_continue {
+                    self.state = State::Continued;
+                } else {
+                    (self.handler)(&self.message);
+                    self.message.clear();
+                    self.state = State::On;
+                }
+            }
+        }
+    }
+
+    /// `warn(...)`, one message from all the pieces
+    pub fn warn(&mut self, pieces: &[&[u8]]) -> Result<()> {
+        let Some((last, init)) = pieces.split_last() else {
+            return Err(Error::NoArguments);
+        };
+        for piece in init {
+            self.warning(piece, true);
+        }
+        self.warning(last, false);
+        Ok(())
+    }
+
+    /// Handle a control message, returning whether `piece` was one.
+    /// Unknown control messages are ignored.
+    fn control(&mut self, piece: &[u8], to_continue: bool) -> bool {
+        if to_continue || !piece.starts_with(b"@") {
+            return false;
+        }
+        match piece {
+            b"@off" => self.state = State::Off,
+            b"@on" => self.state = State::On,
+            _ => {}
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn collecting() -> (Warnings, Rc<RefCell<Vec<String>>>) {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut warnings = Warnings::new();
+        let sink = seen.clone();
+        warnings.set_handler(move |m| sink.borrow_mut().push(String::from_utf8_lossy(m).into()));
+        (warnings, seen)
+    }
+
+    #[test]
+    fn control_messages() {
+        let (mut w, seen) = collecting();
+        w.warn(&[b"ignored while off"]).unwrap();
+        assert!(!w.is_on());
+        w.warn(&[b"@on"]).unwrap();
+        w.warn(&[b"one"]).unwrap();
+        w.warn(&[b"@unknown"]).unwrap();
+        w.warn(&[b"@off"]).unwrap();
+        w.warn(&[b"two"]).unwrap();
+        assert_eq!(*seen.borrow(), ["one"]);
+        assert_eq!(w.warn(&[]), Err(Error::NoArguments));
+    }
+
+    #[test]
+    fn pieces_make_one_message() {
+        let (mut w, seen) = collecting();
+        w.warn(&[b"@on"]).unwrap();
+        w.warn(&[b"a", b"b", b"c"]).unwrap();
+        // Only a whole single-piece message is a control message
+        w.warn(&[b"@off", b"x"]).unwrap();
+        w.warning(b"d", true);
+        w.warning(b"@off", false);
+        assert_eq!(*seen.borrow(), ["abc", "@offx", "d@off"]);
+        assert!(w.is_on());
+    }
+}
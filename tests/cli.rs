@@ -0,0 +1,85 @@
+//! Runs the `lua` binary to check what scripts and shells see: the exit
+//! status and the messages on stderr.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn lua(args: &[&str]) -> Output {
+    lua_in(".", args)
+}
+
+fn lua_in(dir: impl AsRef<std::path::Path>, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lua"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn errors_exit_with_status_1() {
+    let output = lua(&["tests/corpus/does-not-exist.lua"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "lua: cannot open tests/corpus/does-not-exist.lua: No such file or directory\n"
+    );
+
+    let dir = env!("CARGO_TARGET_TMPDIR");
+    std::fs::write(
+        format!("{}/syntax-error.lua", dir),
+        "print(1)\nprint(1 x)\n",
+    )
+    .unwrap();
+    let output = lua_in(dir, &["syntax-error.lua"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "lua: syntax-error.lua:2: ')' expected near 'x'\n"
+    );
+
+    assert_eq!(lua(&["a.lua", "b.lua"]).status.code(), Some(1));
+}
+
+#[test]
+fn valid_scripts_succeed() {
+    let output = lua(&["tests/corpus/print.lua"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+
+    let output = lua(&["--ast", "tests/corpus/print.lua"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("FuncCall"));
+}
+
+/// Run with `input` piped to stdin
+fn lua_stdin(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lua"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn reads_stdin() {
+    assert!(lua_stdin(&["-"], "print(1)\n").status.success());
+    assert!(lua_stdin(&[], "print(1)\n").status.success());
+
+    let output = lua_stdin(&["-"], "print(1)\nprint(1 x)\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "lua: stdin:2: ')' expected near 'x'\n");
+}
@@ -0,0 +1,3 @@
+break
+print(x)
+break
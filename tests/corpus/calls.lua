@@ -0,0 +1,5 @@
+print(1)
+print(1, 2.5, nil, false)
+print()
+assert(check(x))
+collectgarbage()
@@ -0,0 +1,5 @@
+-- a line comment
+print(1) -- trailing comment
+--[[ a block
+comment ]]
+print(2)
@@ -0,0 +1 @@
+print(0x10, 0xff, 1e3, 2.5e-2, 0x1p4)
@@ -0,0 +1,2 @@
+print(f(g(h(1), 2), (x)))
+print((((y))))
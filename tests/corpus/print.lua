@@ -0,0 +1,5 @@
+print(nil)
+print(false)
+print(123)
+print(123456)
+print(123456.0)
\ No newline at end of file
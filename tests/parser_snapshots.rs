@@ -0,0 +1,19 @@
+//! Golden tests for the parser: every `.lua` file in `tests/corpus` is
+//! parsed and its AST (or syntax error) is compared against the stored
+//! snapshot in `tests/snapshots`. Review changes with `cargo insta review`
+//! or accept them all with `INSTA_UPDATE=always cargo test`.
+//!
+//! The corpus only holds syntax the parser supports, plus one deliberate
+//! syntax error. Whole programs live in `benches/lua`, and belong in the
+//! glob below once the parser can read them.
+
+use lua::{lex::Lex, parse::Parser};
+
+#[test]
+fn parse_corpus() {
+    insta::glob!("corpus/*.lua", |path| {
+        let source = std::fs::read_to_string(path).unwrap();
+        let result = Parser::new(Lex::new(&source)).and_then(|mut parser| parser.parse());
+        insta::assert_debug_snapshot!(result);
+    });
+}